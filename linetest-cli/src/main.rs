@@ -1,12 +1,18 @@
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use linetest::{self, CombinedTiming, Datapoint, Evaluation, ThroughputUnit, OUTAGE_MIN_TIMEOUTS};
+use log::info;
+use std::io::stdout;
 use structopt::StructOpt;
-use linetest::{self, Datapoint, Evaluation};
-use std::io::{stdout};
 
-use crossterm::style::{Color, Colors, Print, SetColors};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
 use crossterm::{
-    cursor::{Hide, RestorePosition, SavePosition},
+    cursor::{Hide, RestorePosition, SavePosition, Show},
     execute,
     terminal::{Clear, ClearType},
     Result,
@@ -15,16 +21,347 @@ use crossterm::{
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct LinetestOptions {
- 
+    /// The delay between pings, e.g. 500ms or 5s. Plain numbers are seconds.
     #[structopt(short, long)]
-    ping_delay: Option<u64>,
+    ping_delay: Option<HumanDuration>,
 
     #[structopt(short, long)]
     download_urls: Vec<String>,
+
+    /// Send downloads through a proxy, e.g. http://proxy:8080 or socks5://proxy:1080
+    #[structopt(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Measure the download speed from this iperf3 server (host or host:port) instead of
+    /// downloading files. Needs the iperf3 tool.
+    #[structopt(long, value_name = "SERVER")]
+    iperf3: Option<String>,
+
+    /// Instead of measuring continuously, run a single measurement at this interval, e.g. 15m
+    #[structopt(long)]
+    interval: Option<HumanDuration>,
+
+    /// Run a speed test after every N pings
+    #[structopt(long, value_name = "N")]
+    speedtest_every: Option<usize>,
+
+    /// Download each url over N connections at once, to fill fast lines
+    #[structopt(long, value_name = "N")]
+    connections: Option<usize>,
+
+    /// Time the combined download speed from the start of the first download until the last
+    /// one finished (wall-clock) or by the longest single download (longest-transfer)
+    #[structopt(long, value_name = "TIMING")]
+    combined_timing: Option<CombinedTiming>,
+
+    /// Show latencies above this many ms in yellow
+    #[structopt(long, value_name = "MS", default_value = "50")]
+    latency_warn: f64,
+
+    /// Show latencies above this many ms and timeouts in red
+    #[structopt(long, value_name = "MS", default_value = "150")]
+    latency_bad: f64,
+
+    /// Show download speeds below this many Mbit/s in yellow
+    #[structopt(long, value_name = "MBIT", default_value = "25")]
+    speed_warn: f32,
+
+    /// Show download speeds below this many Mbit/s and failed downloads in red
+    #[structopt(long, value_name = "MBIT", default_value = "5")]
+    speed_bad: f32,
+
+    /// Post the datapoints as JSON to this url in batches, e.g. for a dashboard
+    #[structopt(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Keep pinging during speed tests to measure how much latency rises under load
+    #[structopt(long)]
+    bufferbloat: bool,
+
+    /// Set up connections before timing downloads, so speeds don't include the handshakes
+    #[structopt(long)]
+    warm_connection: bool,
+
+    /// Only monitor the latency, without speed tests using up bandwidth
+    #[structopt(long)]
+    latency_only: bool,
+
+    /// Don't draw live results, only print a summary when the session is stopped with Ctrl-C
+    #[structopt(short, long)]
+    summary: bool,
+
+    /// How to print the summary: text or json
+    #[structopt(long, default_value = "text")]
+    format: SummaryFormat,
+
+    /// Show speeds in mbit (Mbit/s) or mb (MB/s). Logs always store Mbit/s.
+    #[structopt(long, default_value = "mbit")]
+    unit: ThroughputUnit,
+
+    /// Run headless, e.g. under a service manager: log each datapoint instead of drawing,
+    /// start a new logfile every day unless configured otherwise and stop on SIGTERM
+    #[structopt(long)]
+    daemon: bool,
+
+    /// Write logs to this directory instead of the default data directory
+    #[structopt(long, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+
+    /// Don't write a log, keep the session in memory only
+    #[structopt(long)]
+    no_log: bool,
+
+    /// Keep only this many of the most recent datapoints in memory. Everything is still
+    /// logged, but the display and summary only cover these.
+    #[structopt(long)]
+    max_retained: Option<usize>,
+
+    /// Keep only this many of the most recent datapoints, in a single log and in memory,
+    /// e.g. for always-on recording with little storage
+    #[structopt(long)]
+    flight_recorder: Option<usize>,
+
+    /// Leave the measurements of the first part of the session out of the statistics,
+    /// e.g. 30s, so the connection setup doesn't skew them
+    #[structopt(long)]
+    warmup: Option<HumanDuration>,
+
+    /// Tag the log of this session, e.g. "coffee shop wifi"
+    #[structopt(long)]
+    label: Option<String>,
+
+    /// Store the public address, provider and city of the connection in the log.
+    /// This looks them up with ipinfo.io.
+    #[structopt(long)]
+    detect_isp: bool,
+
+    /// Load settings from a TOML file. Flags given on the command line take precedence.
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Write Prometheus metrics to this file after each measurement cycle,
+    /// e.g. for the textfile collector of node_exporter
+    #[structopt(long, parse(from_os_str))]
+    prometheus_textfile: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+impl LinetestOptions {
+    /// The settings given on the command line that apply to the measurement
+    fn overrides(&self) -> linetest::BuilderOverrides {
+        linetest::BuilderOverrides {
+            config: self.config.clone(),
+            data_dir: self.data_dir.clone(),
+            download_urls: self.download_urls.clone(),
+            ping_delay: self.ping_delay.as_ref().map(|HumanDuration(delay)| *delay),
+            proxy: self.proxy.clone(),
+            iperf3_server: self.iperf3.clone(),
+            throughput_ping_ratio: self.speedtest_every,
+            connections: self.connections,
+            combined_timing: self.combined_timing,
+            webhook_url: self.webhook.clone(),
+            bufferbloat: self.bufferbloat,
+            warm_connection: self.warm_connection,
+            latency_only: self.latency_only,
+            no_log: self.no_log,
+            max_retained: self.max_retained,
+            flight_recorder: self.flight_recorder,
+            warmup: self.warmup.as_ref().map(|HumanDuration(warmup)| *warmup),
+            label: self.label.clone(),
+            detect_isp: self.detect_isp,
+        }
+    }
+}
+
+/// How the summary of a session is printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SummaryFormat {
+    /// A table for reading
+    Text,
+    /// A single JSON object for scripts
+    Json,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(SummaryFormat::Text),
+            "json" => Ok(SummaryFormat::Json),
+            other => anyhow::bail!("Unknown format {:?}, use text or json", other),
+        }
+    }
+}
+
+/// Limits for coloring the live display green, yellow or red
+#[derive(Debug, Clone, Copy)]
+struct ColorThresholds {
+    latency_warn_ms: f64,
+    latency_bad_ms: f64,
+    speed_warn_mbit: f32,
+    speed_bad_mbit: f32,
+}
+
+impl ColorThresholds {
+    fn from_options(opts: &LinetestOptions) -> Self {
+        Self {
+            latency_warn_ms: opts.latency_warn,
+            latency_bad_ms: opts.latency_bad,
+            speed_warn_mbit: opts.speed_warn,
+            speed_bad_mbit: opts.speed_bad,
+        }
+    }
+
+    /// The color of a latency or download speed, timeouts and failed downloads are red.
+    /// Other datapoints are not colored.
+    fn color(&self, dp: &Datapoint) -> Option<Color> {
+        match dp {
+            Datapoint::Latency(_, Some(latency), ..) => {
+                let ms = latency.as_secs_f64() * 1000.;
+                Some(if ms > self.latency_bad_ms {
+                    Color::Red
+                } else if ms > self.latency_warn_ms {
+                    Color::Yellow
+                } else {
+                    Color::Green
+                })
+            }
+            Datapoint::ThroughputDown(Some(mbit), _) => Some(if *mbit < self.speed_bad_mbit {
+                Color::Red
+            } else if *mbit < self.speed_warn_mbit {
+                Color::Yellow
+            } else {
+                Color::Green
+            }),
+            Datapoint::Latency(_, None, ..) | Datapoint::ThroughputDown(None, _) => {
+                Some(Color::Red)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Print a datapoint in the color given by the `thresholds`, with speeds in `unit`
+fn print_colored(
+    prefix: &str,
+    dp: &Datapoint,
+    thresholds: &ColorThresholds,
+    unit: ThroughputUnit,
+) -> Result<()> {
+    match thresholds.color(dp) {
+        Some(color) => execute!(
+            stdout(),
+            Print(prefix),
+            SetForegroundColor(color),
+            Print(format!("{}", dp.display(unit))),
+            ResetColor
+        ),
+        None => execute!(stdout(), Print(format!("{}{}", prefix, dp.display(unit)))),
+    }
+}
+
+/// A duration given on the command line, like `500ms`, `30s`, `15m` or `1h`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HumanDuration(Duration);
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        parse_duration(s).map(HumanDuration)
+    }
+}
+
+/// Parse a number with an optional `ms`, `s`, `m` or `h` suffix. Numbers without a suffix are seconds.
+fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse().map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid duration {:?}, expected e.g. 500ms, 30s, 15m or 1h",
+            s
+        )
+    })?;
+    let secs = match unit.trim() {
+        "ms" => value / 1000.,
+        "" | "s" => value,
+        "m" => value * 60.,
+        "h" => value * 60. * 60.,
+        other => anyhow::bail!(
+            "Unknown unit {:?} in duration {:?}, use ms, s, m or h",
+            other,
+            s
+        ),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| anyhow::anyhow!("Duration {:?} is too long", s))
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Convert a recorded log to another format
+    Export {
+        /// Write the log as CSV to this file
+        #[structopt(long, parse(from_os_str))]
+        csv: PathBuf,
+
+        /// Only export datapoints recorded at or after this time, in seconds since the unix epoch
+        #[structopt(long)]
+        from: Option<u64>,
+
+        /// Only export datapoints recorded before this time, in seconds since the unix epoch
+        #[structopt(long)]
+        until: Option<u64>,
+
+        /// The log to export
+        #[structopt(parse(from_os_str))]
+        logfile: PathBuf,
+    },
+    /// Print the summary of a recorded log without measuring
+    Stats {
+        /// The log to summarize
+        #[structopt(parse(from_os_str))]
+        logfile: PathBuf,
+    },
+    /// Show the routers on the way to a target and the latency to each. Usually needs root.
+    Trace {
+        /// The IP address or hostname to trace
+        target: String,
+    },
+    /// Combine several recorded logs into one, sorted by time
+    Merge {
+        /// The logs to merge
+        #[structopt(parse(from_os_str), required = true)]
+        logfiles: Vec<PathBuf>,
+
+        /// Write the merged log to this file
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Convert the output of `ping` or the JSON results of a speedtest tool into a log
+    Import {
+        /// The saved output of the other tool
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Write the log to this file
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
 }
 
 /// Primitive function to draw the results
-fn draw_ui(result: &linetest::MeasurementResult) -> Result<()> {
+fn draw_ui(
+    result: &linetest::MeasurementResult,
+    thresholds: &ColorThresholds,
+    unit: ThroughputUnit,
+    latency_only: bool,
+) -> Result<()> {
     execute!(
         stdout(),
         //SetColors(Colors::new(Green, Black)),
@@ -33,14 +370,13 @@ fn draw_ui(result: &linetest::MeasurementResult) -> Result<()> {
         Hide
     )?;
 
-
     let mut dp_ping: Option<&Datapoint> = None;
     let mut dp_dl: Option<&Datapoint> = None;
 
-
-    for res in result {
+    // the warm-up is shown live, it is only left out of the statistics
+    for res in result.iter().map(Datapoint::measurement) {
         match res {
-            Datapoint::Latency(_l, _t) => {
+            Datapoint::Latency(..) => {
                 dp_ping = Some(res);
             }
             Datapoint::ThroughputDown(_tp, _t) => {
@@ -51,82 +387,527 @@ fn draw_ui(result: &linetest::MeasurementResult) -> Result<()> {
         }
     }
 
-
-
     match dp_ping {
-        Some(dp) => {
-            execute!(
-                stdout(),
-                Print(format!("{}", dp)),
-            )?;
-        },
+        Some(dp) => print_colored("", dp, thresholds, unit)?,
         None => {
-            execute!(
-                stdout(),
-                Print("Please wait..."),
-            )?;
+            execute!(stdout(), Print("Please wait..."),)?;
         }
     }
 
     match dp_dl {
-        Some(dp) => {
-            execute!(
-                stdout(),
-                Print(format!("\n{}", dp)),
-            )?;
-        },
+        Some(dp) => print_colored("\n", dp, thresholds, unit)?,
+        None if latency_only => execute!(stdout(), Print("\nSpeed:\tNot measured"))?,
         None => {
-            execute!(
-                stdout(),
-                Print("\nSpeed:\tPlease wait..."),
-            )?;
+            execute!(stdout(), Print("\nSpeed:\tPlease wait..."),)?;
         }
     }
-    
 
     execute!(
         stdout(),
-        RestorePosition
+        Print(format!(
+            "\nJitter:\t{:.2} ms (std. deviation {:.2} ms)",
+            result.jitter().as_secs_f32() * 1000.,
+            result.latency_stddev().as_secs_f32() * 1000.
+        )),
     )?;
 
+    execute!(stdout(), RestorePosition)?;
+
     Ok(())
 }
 
 fn main() {
-    std::env::set_var("RUST_LOG", "warning");
+    let mut opts = LinetestOptions::from_args();
+
+    std::env::set_var("RUST_LOG", if opts.daemon { "info" } else { "warning" });
     // #[cfg(debug_assertions)]
     // std::env::set_var("RUST_LOG", "info");
 
     let _ = env_logger::try_init();
 
-    let opts = LinetestOptions::from_args();
+    match opts.command.take() {
+        Some(Command::Export {
+            csv,
+            from,
+            until,
+            logfile,
+        }) => exit_on_error(export(&csv, from, until, &logfile)),
+        Some(Command::Merge { logfiles, output }) => exit_on_error(merge(&logfiles, &output)),
+        Some(Command::Import { input, output }) => exit_on_error(import(&input, &output)),
+        Some(Command::Stats { logfile }) => exit_on_error(stats(&logfile, opts.format, opts.unit)),
+        Some(Command::Trace { target }) => exit_on_error(trace(&target)),
+        None => record(opts),
+    }
+}
+
+/// Print an error and exit with a non-zero code
+fn exit_on_error(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Convert a recorded log to CSV
+fn export(csv: &Path, from: Option<u64>, until: Option<u64>, logfile: &Path) -> anyhow::Result<()> {
+    let mut result: linetest::MeasurementResult = vec![];
+    result.load(logfile)?;
+    if from.is_some() || until.is_some() {
+        let start = UNIX_EPOCH + Duration::from_secs(from.unwrap_or_default());
+        let end = until
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap_or_else(SystemTime::now);
+        result = result.between(start, end);
+    }
+    result.save_csv(csv)?;
+    println!("Exported {} datapoints to {}", result.len(), csv.display());
+    Ok(())
+}
+
+/// Combine several logs into one
+fn merge(logfiles: &[PathBuf], output: &Path) -> anyhow::Result<()> {
+    let merged = linetest::merge(logfiles)?;
+    merged.save(output)?;
+    println!(
+        "Merged {} datapoints from {} logs to {}",
+        merged.len(),
+        logfiles.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Convert the output of another measurement tool into a log
+fn import(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let imported = linetest::import(&std::fs::read_to_string(input)?)?;
+    imported.save(output)?;
+    println!(
+        "Imported {} datapoints to {}",
+        imported.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Summarize a recorded log
+fn stats(logfile: &Path, format: SummaryFormat, unit: ThroughputUnit) -> anyhow::Result<()> {
+    let mut result: linetest::MeasurementResult = vec![];
+    result.load(logfile)?;
+    if format == SummaryFormat::Text {
+        print!("{}", logfile.display());
+        if let Some(metadata) = linetest::load_metadata(logfile)? {
+            print!(", {}", metadata);
+        }
+        println!();
+    }
+    print_summary(&result, format, unit);
+    Ok(())
+}
+
+/// Print each hop on the way to `target`, `*` for routers that didn't answer
+fn trace(target: &str) -> anyhow::Result<()> {
+    for hop in linetest::traceroute(target)? {
+        let address = hop
+            .address
+            .map(|a| a.to_string())
+            .unwrap_or_else(|| "*".to_string());
+        println!("{:>2}  {:<39}  {}", hop.ttl, address, format_ms(hop.rtt));
+    }
+    Ok(())
+}
+
+/// Replace the metrics file atomically, so a scraper never reads a partial file
+fn write_prometheus(result: &linetest::MeasurementResult, textfile: &Path) -> anyhow::Result<()> {
+    let tmp = textfile.with_extension("prom.tmp");
+    std::fs::write(&tmp, result.to_prometheus())?;
+    std::fs::rename(&tmp, textfile)?;
+    Ok(())
+}
 
-    let mut measurement = linetest::MeasurementBuilder::default();
+/// Measure continuously and draw the results until interrupted
+fn record(opts: LinetestOptions) {
+    let thresholds = ColorThresholds::from_options(&opts);
+    let mut measurement = match linetest::MeasurementBuilder::from_overrides(&opts.overrides()) {
+        Ok(measurement) => measurement,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    if !opts.download_urls.is_empty() {
-        measurement.downloads_urls = opts.download_urls
+    // a flight recorder keeps a single log
+    if opts.daemon
+        && measurement.rotation == linetest::RotationPolicy::Never
+        && measurement.log_capacity.is_none()
+    {
+        measurement.rotation = linetest::RotationPolicy::Daily;
     }
 
-    if let Some(s) = opts.ping_delay {
-        measurement.ping_delay = Duration::from_secs(s);
+    if let Err(e) = measurement.validate() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
     }
+    // measuring is still useful if the results can't be saved
+    if let Err(e) = measurement.check_writable() {
+        eprintln!("Warning: {:#}, this session is not saved", e);
+        measurement = measurement.without_log();
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupt_handler_flag = interrupted.clone();
+    ctrlc::set_handler(move || interrupt_handler_flag.store(true, Ordering::Relaxed))
+        .expect("Can't install Ctrl-C handler");
 
-    let receiver = measurement.run_until_receiver_drops().unwrap();
+    if let Some(HumanDuration(interval)) = opts.interval {
+        record_scheduled(
+            &mut measurement,
+            interval,
+            &interrupted,
+            opts.format,
+            opts.unit,
+        );
+        return;
+    }
+
+    let mut handle = measurement.run_until_receiver_drops().unwrap();
+    let latency_only = !measurement.measures_throughput();
     let mut measurement_result = vec![];
+    let mut log_failed = false;
 
-    println!("[[[ Linetest ]]]");
-    if let Some(log) = &measurement.logfile {
-        println!("=> This session is recorded to {}", log.to_string_lossy());
+    if opts.daemon {
+        if let Some(log) = &measurement.logfile {
+            info!("Recording to {}", log.display());
+        }
+    } else {
+        println!("[[[ Linetest ]]]");
+        match &measurement.logfile {
+            Some(log) => println!("=> This session is recorded to {}", log.to_string_lossy()),
+            None => println!("=> This session is not saved"),
+        }
+        if opts.summary {
+            println!("=> Press Ctrl-C to stop and print the summary");
+        }
     }
 
-    loop {
-        for dp in &receiver {
-            measurement_result.push(dp);
-            if let Some(log) = &measurement.logfile {
+    while !interrupted.load(Ordering::Relaxed) {
+        match handle.recv_timeout(Duration::from_millis(200)) {
+            Ok(dp) => {
+                // without speed tests, each ping completes a cycle
+                let cycle_done = match dp.measurement() {
+                    Datapoint::ThroughputDown(..) => true,
+                    Datapoint::Latency(..) => latency_only,
+                    _ => false,
+                };
                 // save each entry
-                measurement_result.save(log).unwrap();
+                match measurement.append_to_log(&dp) {
+                    // a daemon only keeps the datapoints of the current logfile
+                    Ok(true) if opts.daemon => measurement_result.clear(),
+                    Ok(_) => log_failed = false,
+                    // only report when writing starts failing, not for every datapoint
+                    Err(e) if !log_failed => {
+                        log_failed = true;
+                        eprintln!("Error: can't write log: {:#}", e);
+                    }
+                    Err(_) => (),
+                }
+                measurement.push_retained(&mut measurement_result, dp);
+                if let (true, Some(textfile)) = (cycle_done, &opts.prometheus_textfile) {
+                    if let Err(e) = write_prometheus(&measurement_result, textfile) {
+                        eprintln!("Error: can't write {}: {:#}", textfile.display(), e);
+                    }
+                }
+                if opts.daemon {
+                    if let Some(dp) = measurement_result.last() {
+                        info!("{}", dp.display(opts.unit));
+                    }
+                } else if !opts.summary {
+                    draw_ui(&measurement_result, &thresholds, opts.unit, latency_only).unwrap();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    if let Some(failure) = handle.failure() {
+        eprintln!("Error: the measurement stopped unexpectedly: {}", failure);
+    }
+
+    if opts.daemon {
+        info!("Stopping");
+        handle.stop();
+        return;
+    }
+
+    if !opts.summary {
+        println!("\n\n");
+    }
+    println!("Stopping...");
+    handle.stop();
+    let _ = execute!(stdout(), Show);
+    print_summary(&measurement_result, opts.format, opts.unit);
+}
+
+/// Run a single measurement every `interval` and print its results until interrupted
+fn record_scheduled(
+    measurement: &mut linetest::MeasurementBuilder,
+    interval: Duration,
+    interrupted: &AtomicBool,
+    format: SummaryFormat,
+    unit: ThroughputUnit,
+) {
+    let receiver = match measurement.run_scheduled(interval) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    match &measurement.logfile {
+        Some(log) => println!(
+            "=> Measuring every {:?}, recorded to {}",
+            interval,
+            log.display()
+        ),
+        None => println!("=> Measuring every {:?}, not saved", interval),
+    }
+    let mut measurement_result = vec![];
+    let mut log_failed = false;
+    while !interrupted.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(Duration::from_millis(200)) {
+            Ok(result) => {
+                for dp in result {
+                    match measurement.append_to_log(&dp) {
+                        Ok(_) => log_failed = false,
+                        Err(e) if !log_failed => {
+                            log_failed = true;
+                            eprintln!("Error: can't write log: {:#}", e);
+                        }
+                        Err(_) => (),
+                    }
+                    println!("{}", dp.display(unit));
+                    measurement.push_retained(&mut measurement_result, dp);
+                }
             }
-            draw_ui(&measurement_result).unwrap();
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
         }
     }
+    print_summary(&measurement_result, format, unit);
+}
+
+/// Format an optional duration in milliseconds
+fn format_ms(duration: Option<Duration>) -> String {
+    duration
+        .map(|d| format!("{:.1} ms", d.as_secs_f64() * 1000.))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Describe the outages of a session, e.g. "3, longest 42 s"
+fn format_outages(result: &linetest::MeasurementResult) -> String {
+    let outages = result.outages(OUTAGE_MIN_TIMEOUTS);
+    let longest = outages
+        .iter()
+        .map(|(start, end, _)| end.duration_since(*start).unwrap_or_default())
+        .max();
+    match longest {
+        Some(longest) => format!("{}, longest {} s", outages.len(), longest.as_secs()),
+        None => "none".to_string(),
+    }
+}
+
+/// Print a digest of the session as an aligned table with speeds in `unit`
+fn print_summary(
+    result: &linetest::MeasurementResult,
+    format: SummaryFormat,
+    unit: ThroughputUnit,
+) {
+    if format == SummaryFormat::Json {
+        println!("{}", result.summary_json());
+        return;
+    }
+    let rows = vec![
+        (
+            "Duration",
+            format!("{:.1} s", result.duration().as_secs_f64()),
+        ),
+        ("Samples", result.len().to_string()),
+        (
+            "Quality score",
+            format!("{:.1} / 5", result.quality_score()),
+        ),
+        ("Mean latency", format_ms(Some(result.mean_latency()))),
+        ("Median latency", format_ms(result.latency_p50())),
+        ("95th percentile latency", format_ms(result.latency_p95())),
+        ("Jitter", format_ms(Some(result.jitter()))),
+        (
+            "Latency trend",
+            format!("{:+.1} ms/min", result.latency_trend()),
+        ),
+        ("Bufferbloat", format_ms(result.bufferbloat_ms())),
+        (
+            "Mean download speed",
+            format!("{:.1} {}", unit.convert(result.mean_dl()), unit),
+        ),
+        (
+            "Download speed trend",
+            format!(
+                "{:+.1} {} per min",
+                unit.convert(result.download_trend() as f32),
+                unit
+            ),
+        ),
+        (
+            "Mean upload speed",
+            format!("{:.1} {}", unit.convert(result.mean_ul()), unit),
+        ),
+        (
+            "Data downloaded",
+            format!(
+                "{:.1} MB",
+                result.total_bytes_downloaded() as f64 / 1000. / 1000.
+            ),
+        ),
+        ("Timeouts", result.timeouts().to_string()),
+        ("Reordered replies", result.reordered_count().to_string()),
+        (
+            "Timeout percentage",
+            format!("{:.1} %", result.timeouts_for_session() * 100.),
+        ),
+        (
+            "Availability",
+            format!("{:.2} %", result.availability() * 100.),
+        ),
+        ("Outages", format_outages(result)),
+    ];
+
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default();
+    println!();
+    for (name, value) in rows {
+        println!("{:<width$}  {}", name, value, width = width);
+    }
+
+    let histogram = format_histogram(&result.latency_histogram(HISTOGRAM_BUCKET_MS));
+    if !histogram.is_empty() {
+        println!("\nLatency distribution");
+        print!("{}", histogram);
+    }
+}
+
+/// Width of a latency histogram bucket in ms
+const HISTOGRAM_BUCKET_MS: f64 = 10.;
+
+/// Longest bar of the latency histogram in characters
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Draw a latency histogram as one line of `#` per bucket. The last bucket is open-ended.
+fn format_histogram(histogram: &[(f64, usize)]) -> String {
+    let max = histogram
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    let labels = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, (lower, _))| match histogram.get(i + 1) {
+            Some((upper, _)) => format!("{}-{} ms", lower, upper),
+            None => format!(">= {} ms", lower),
+        })
+        .collect::<Vec<_>>();
+    let width = labels.iter().map(String::len).max().unwrap_or_default();
+    labels
+        .iter()
+        .zip(histogram)
+        .map(|(label, (_, count))| {
+            format!(
+                "{:>width$} | {} {}\n",
+                label,
+                "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max)),
+                count,
+                width = width
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(60 * 60));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("5").unwrap(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn histogram() {
+        assert_eq!(format_histogram(&[]), "");
+        assert_eq!(
+            format_histogram(&[(0., 2), (10., 0), (20., 1)]),
+            " 0-10 ms | ######################################## 2\n10-20 ms |  0\n>= 20 ms | #################### 1\n"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let thresholds = ColorThresholds {
+            latency_warn_ms: 50.,
+            latency_bad_ms: 150.,
+            speed_warn_mbit: 25.,
+            speed_bad_mbit: 5.,
+        };
+        let ping =
+            |ms: Option<u64>| Datapoint::add_latency("8.8.8.8", ms.map(Duration::from_millis));
+        assert_eq!(thresholds.color(&ping(Some(20))), Some(Color::Green));
+        assert_eq!(thresholds.color(&ping(Some(100))), Some(Color::Yellow));
+        assert_eq!(thresholds.color(&ping(Some(200))), Some(Color::Red));
+        assert_eq!(thresholds.color(&ping(None)), Some(Color::Red));
+        assert_eq!(
+            thresholds.color(&Datapoint::add_tp_down(Some(100.))),
+            Some(Color::Green)
+        );
+        assert_eq!(
+            thresholds.color(&Datapoint::add_tp_down(Some(10.))),
+            Some(Color::Yellow)
+        );
+        assert_eq!(
+            thresholds.color(&Datapoint::add_tp_down(None)),
+            Some(Color::Red)
+        );
+        assert_eq!(thresholds.color(&Datapoint::add_tp_up(Some(1.))), None);
+    }
+
+    #[test]
+    fn summary_formats() {
+        assert_eq!(
+            "json".parse::<SummaryFormat>().unwrap(),
+            SummaryFormat::Json
+        );
+        assert_eq!(
+            "text".parse::<SummaryFormat>().unwrap(),
+            SummaryFormat::Text
+        );
+        assert!("yaml".parse::<SummaryFormat>().is_err());
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration("1000000000000000000000h").is_err());
+    }
 }
@@ -1,35 +1,227 @@
+use chrono::{Local, TimeZone};
 use eframe::egui::plot::{Legend, Points};
-use eframe::egui::{Color32, FontData, FontDefinitions, FontFamily, TextStyle, Visuals};
+use eframe::egui::{Color32, FontData, FontDefinitions, FontFamily, TextStyle};
 use eframe::{egui, epi};
-use egui::plot::{HLine, Line, Plot, Value, Values};
-use linetest::{self, Datapoint, Evaluation, MeasurementBuilder};
-use log::info;
+use egui::plot::{Bar, BarChart, HLine, Line, Plot, Value, Values};
+use linetest::{
+    self, AddressFamily, CombinedTiming, Datapoint, Evaluation, MeasurementBuilder,
+    MeasurementHandle, Metadata, PingBackend, ThroughputUnit,
+};
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
 use std::ffi::OsStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::{path::PathBuf, sync::mpsc::Receiver};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Colors for the latency series of additional ping targets
+const TARGET_COLORS: [Color32; 3] = [
+    Color32::from_rgb(0, 160, 255),
+    Color32::from_rgb(230, 80, 80),
+    Color32::from_rgb(80, 200, 120),
+];
+
+/// Number of latency changes between pings of the same target shown in the jitter sparkline
+const SPARKLINE_SAMPLES: usize = 30;
+
+/// The most recent changes in latency, recomputed only when new datapoints arrive
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+pub struct JitterSparkline {
+    /// Length and last timestamp of the datapoints the deltas were computed from
+    computed_for: Option<(usize, SystemTime)>,
+    /// Change in latency from one ping to the next, in ms
+    deltas: Vec<f64>,
+}
+
+impl JitterSparkline {
+    fn update(&mut self, datapoints: &[Datapoint]) {
+        let key = datapoints
+            .last()
+            .map(|dp| (datapoints.len(), dp.timestamp()));
+        if key == self.computed_for {
+            return;
+        }
+        self.computed_for = key;
+        // walking backwards, the later latency of each target, as targets can have very
+        // different latencies
+        let mut later: BTreeMap<&str, f64> = BTreeMap::new();
+        let mut deltas = vec![];
+        for dp in datapoints.iter().rev() {
+            if deltas.len() == SPARKLINE_SAMPLES {
+                break;
+            }
+            if let Datapoint::Latency(target, Some(latency), ..) = dp {
+                let ms = latency.as_secs_f64() * 1000.;
+                if let Some(next) = later.insert(target.as_str(), ms) {
+                    deltas.push(next - ms);
+                }
+            }
+        }
+        deltas.reverse();
+        self.deltas = deltas;
+    }
+
+    /// The jitter of the recent pings in ms
+    fn jitter_ms(&self) -> f64 {
+        if self.deltas.is_empty() {
+            return 0.0;
+        }
+        self.deltas.iter().map(|d| d.abs()).sum::<f64>() / self.deltas.len() as f64
+    }
+}
+
+/// Time span over which the packet loss is summarized in the loss plot
+const LOSS_BUCKET: Duration = Duration::from_secs(60);
+
+/// Green for a good, yellow for a fair and red for a poor quality score
+fn quality_color(score: f32) -> Color32 {
+    if score >= 4.0 {
+        Color32::from_rgb(80, 200, 120)
+    } else if score >= 3.0 {
+        Color32::from_rgb(255, 208, 0)
+    } else {
+        Color32::RED
+    }
+}
+
+/// An arrow pointing in the direction of a trend, see [Evaluation::latency_trend]
+fn trend_arrow(trend: f64) -> &'static str {
+    if trend > 0.0 {
+        "↑"
+    } else if trend < 0.0 {
+        "↓"
+    } else {
+        "→"
+    }
+}
+
+/// Append `dp` to the log of `measurement`. Only the first failure of a recording is
+/// reported, so an unwritable data directory doesn't bring up a warning for every datapoint.
+fn append_to_log(
+    measurement: &mut MeasurementBuilder,
+    dp: &Datapoint,
+    log_error: &mut Option<String>,
+    log_failed: &mut bool,
+) {
+    if let Err(e) = measurement.append_to_log(dp) {
+        if !*log_failed {
+            error!("Can't write log: {:#}", e);
+            *log_error = Some(format!("{:#}", e));
+            *log_failed = true;
+        }
+    }
+}
+
+/// Time left as `h:mm:ss`, or `m:ss` below an hour
+fn format_countdown(left: Duration) -> String {
+    let secs = left.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// The most datapoints drawn in the plots, longer sessions are downsampled
+const MAX_PLOT_POINTS: usize = 2000;
+
+/// Width of a latency histogram bucket in ms
+const HISTOGRAM_BUCKET_MS: f64 = 10.;
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "persistence", serde(default))] // if we add new fields, give them default values when deserializing old state
 pub struct LinetestApp {
-    pub receiver: Option<Receiver<Datapoint>>,
+    pub receiver: Option<MeasurementHandle>,
     pub datapoints: Vec<Datapoint>,
     pub logs: Vec<PathBuf>,
     pub log_index: usize,
+    /// Logs selected to be merged
+    pub merge_selection: Vec<PathBuf>,
+    /// Indices of the logs to compare, before and after
+    pub compare_indices: (usize, usize),
+    /// The two sessions being compared
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub comparison: Option<(Vec<Datapoint>, Vec<Datapoint>)>,
     pub dark_mode: bool,
+    /// Number of latency samples averaged for the smoothed line. 1 disables smoothing.
+    pub smoothing_window: usize,
+    pub jitter_sparkline: JitterSparkline,
+    /// Label the time axis of plots with the clock time instead of seconds since the start
+    pub clock_time_axis: bool,
+    /// Show speeds in Mbit/s or MB/s
+    pub throughput_unit: ThroughputUnit,
     pub measurement: MeasurementBuilder,
+    /// Why the last recording could not be started
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub start_error: Option<String>,
+    /// The recording is paused and can be resumed into the same logfile
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub paused: bool,
+    /// Stop recordings after this time, `None` records until stopped
+    pub record_for: Option<Duration>,
+    /// When a recording for a set time ends
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub recording_deadline: Option<Instant>,
+    /// The time left of a paused recording for a set time
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub paused_remaining: Option<Duration>,
+    /// The result of a single measurement started with the quick test button, while it runs
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub quick_test: Option<Receiver<Result<Vec<Datapoint>, String>>>,
+    /// Why a log dropped onto the window could not be opened
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub open_error: Option<String>,
+    /// Why the recording can't be saved, shown until dismissed
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub log_error: Option<String>,
+    /// Saving the recording failed and was reported already
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub log_failed: bool,
+    /// Where the loaded log was recorded
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub log_metadata: Option<Metadata>,
+    /// The datapoints of a log being replayed, see [MeasurementBuilder::replay]
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub replay: Option<Receiver<Datapoint>>,
+    /// How many times faster than recorded a log is replayed
+    pub replay_speed: f32,
 }
 
 impl Default for LinetestApp {
     fn default() -> Self {
+        let measurement = MeasurementBuilder::new()
+            .with_aws_payload()
+            .with_ping_delay(1);
         Self {
             receiver: None,
             datapoints: vec![],
-            logs: MeasurementBuilder::get_logs().unwrap_or_default(),
+            logs: measurement.logs().unwrap_or_default(),
             log_index: 0,
+            merge_selection: vec![],
+            compare_indices: (0, 0),
+            comparison: None,
             dark_mode: false,
-            measurement: MeasurementBuilder::new()
-                .with_aws_payload()
-                .with_ping_delay(1),
+            smoothing_window: 1,
+            jitter_sparkline: JitterSparkline::default(),
+            clock_time_axis: true,
+            throughput_unit: ThroughputUnit::default(),
+            measurement,
+            start_error: None,
+            paused: false,
+            record_for: None,
+            recording_deadline: None,
+            paused_remaining: None,
+            quick_test: None,
+            open_error: None,
+            log_error: None,
+            log_failed: false,
+            log_metadata: None,
+            replay: None,
+            replay_speed: 1.0,
         }
     }
 }
@@ -74,7 +266,6 @@ impl epi::App for LinetestApp {
 
         let mut style: egui::Style = (*ctx.style()).clone();
 
-
         style.text_styles.get_mut(&TextStyle::Body).unwrap().size = 20.;
         style.text_styles.get_mut(&TextStyle::Button).unwrap().size = 20.;
         style.text_styles.get_mut(&TextStyle::Small).unwrap().size = 15.;
@@ -83,14 +274,33 @@ impl epi::App for LinetestApp {
     }
 
     /// Called each time the UI needs repainting, which may be many times per second.
-    fn update(&mut self, ctx: &egui::Context, _frame: &epi::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &epi::Frame) {
         let Self {
             receiver,
             datapoints,
             logs,
             log_index,
+            merge_selection,
+            compare_indices,
+            comparison,
             dark_mode,
+            smoothing_window,
+            jitter_sparkline,
+            clock_time_axis,
+            throughput_unit,
             measurement,
+            start_error,
+            paused,
+            record_for,
+            recording_deadline,
+            paused_remaining,
+            quick_test,
+            open_error,
+            log_error,
+            log_failed,
+            log_metadata,
+            replay,
+            replay_speed,
         } = self;
 
         let line_color = Color32::from_rgb(255, 208, 0);
@@ -98,48 +308,246 @@ impl epi::App for LinetestApp {
         ctx.request_repaint();
         if let Some(valid_receiver) = receiver {
             for dp in valid_receiver.try_iter() {
-                datapoints.push(dp);
-                if let Some(log) = &measurement.logfile {
-                    let _ = datapoints.save(&log);
+                append_to_log(measurement, &dp, log_error, log_failed);
+                measurement.push_retained(datapoints, dp);
+            }
+            if let Some(failure) = valid_receiver.failure() {
+                *start_error = Some(format!("The measurement stopped unexpectedly: {}", failure));
+                *receiver = None;
+            }
+        }
+
+        // a replay only animates the plots, nothing is logged
+        if let Some(replay_receiver) = replay {
+            loop {
+                match replay_receiver.try_recv() {
+                    Ok(dp) => measurement.push_retained(datapoints, dp),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        *replay = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(quick_receiver) = quick_test {
+            match quick_receiver.try_recv() {
+                Ok(Ok(result)) => {
+                    for dp in result {
+                        // part of the session if a recording is running
+                        if receiver.is_some() {
+                            append_to_log(measurement, &dp, log_error, log_failed);
+                        }
+                        measurement.push_retained(datapoints, dp);
+                    }
+                    *quick_test = None;
+                }
+                Ok(Err(e)) => {
+                    *start_error = Some(format!("The quick test failed: {}", e));
+                    *quick_test = None;
                 }
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => {
+                    *start_error = Some("The quick test stopped unexpectedly".to_string());
+                    *quick_test = None;
+                }
+            }
+        }
+
+        // logs dragged onto the window, e.g. ones shared by others
+        let dropped_files = ctx
+            .input()
+            .raw
+            .dropped_files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .collect::<Vec<_>>();
+        for path in dropped_files {
+            match open_log(&path) {
+                Ok(loaded) => {
+                    *receiver = None;
+                    *replay = None;
+                    *paused = false;
+                    *datapoints = loaded;
+                    *log_metadata = linetest::load_metadata(&path).unwrap_or_default();
+                    *open_error = None;
+                    info!(
+                        "Loaded {} data points from {}",
+                        datapoints.len(),
+                        path.display()
+                    );
+                }
+                Err(e) => *open_error = Some(e),
             }
         }
 
+        if let Some(e) = open_error.clone() {
+            egui::Window::new("Can't open log")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-10., -10.])
+                .show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, e);
+                    if ui.button("OK").clicked() {
+                        *open_error = None;
+                    }
+                });
+        }
+
+        if let Some(e) = log_error.clone() {
+            egui::Window::new("Can't save the recording")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::RIGHT_BOTTOM, [-10., -10.])
+                .show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, e);
+                    ui.label("The measurement goes on without being saved.");
+                    if ui.button("OK").clicked() {
+                        *log_error = None;
+                    }
+                });
+        }
+
         if *dark_mode {
             ctx.set_visuals(egui::Visuals::dark());
         } else {
             ctx.set_visuals(egui::Visuals::light());
         }
 
+        // speeds are stored in Mbit/s and only converted for display
+        let unit = *throughput_unit;
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
             egui::menu::bar(ui, |ui| {
-                // egui::menu::menu(ui, "File", |ui| {
-                //     if ui.button("Quit").clicked() {
-                //         frame.quit();
-                //     }
-                //     if ui.button("Toggle light/dark").clicked() {
-                //         *dark_mode = !*dark_mode;
-                //     }
-                // });
+                ui.menu_button("File", |ui| {
+                    if ui.button("Export CSV...").clicked() {
+                        ui.close_menu();
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("CSV", &["csv"])
+                            .set_file_name("linetest.csv")
+                            .save_file()
+                        {
+                            match datapoints.save_csv(&path) {
+                                Ok(_) => info!(
+                                    "Exported {} data points to {}",
+                                    datapoints.len(),
+                                    path.display()
+                                ),
+                                Err(e) => error!("Can't export to {}: {}", path.display(), e),
+                            }
+                        }
+                    }
+                    if ui.button("Toggle light/dark").clicked() {
+                        ui.close_menu();
+                        *dark_mode = !*dark_mode;
+                    }
+                    if ui.button("Quit").clicked() {
+                        frame.quit();
+                    }
+                });
             });
         });
 
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
             ui.label("Info");
+            if let Some(metadata) = log_metadata {
+                ui.label(metadata.to_string());
+            }
+
+            if datapoints.iter().any(Datapoint::is_latency) {
+                let score = datapoints.quality_score();
+                ui.label(
+                    egui::RichText::new(format!("Quality {:.1} / 5", score))
+                        .heading()
+                        .color(quality_color(score)),
+                );
+            }
 
             ui.label(format!("{} samples", datapoints.len()));
             ui.label(format!("Time: {:.1}s", datapoints.duration().as_secs_f64()));
-            ui.label(format!("{:.1} Mbit/s down", datapoints.mean_dl()));
+            ui.label(format!(
+                "{:.1} {} down",
+                unit.convert(datapoints.mean_dl()),
+                unit
+            ));
+            ui.label(format!(
+                "{:.1}–{:.1} {} down",
+                unit.convert(datapoints.min_dl()),
+                unit.convert(datapoints.max_dl()),
+                unit
+            ));
+            ui.label(format!(
+                "{} {:+.1} {} per min",
+                trend_arrow(datapoints.download_trend()),
+                unit.convert(datapoints.download_trend() as f32),
+                unit
+            ));
+            ui.label(format!(
+                "{:.1} {} up",
+                unit.convert(datapoints.mean_ul()),
+                unit
+            ));
+            ui.label(format!(
+                "{:.1} MB downloaded",
+                datapoints.total_bytes_downloaded() as f64 / 1000. / 1000.
+            ));
             ui.label(format!(
                 "{:.1} ms mean latency",
                 datapoints.mean_latency().as_millis()
             ));
+            ui.label(format!(
+                "{} {:+.1} ms/min latency trend",
+                trend_arrow(datapoints.latency_trend()),
+                datapoints.latency_trend()
+            ));
+            ui.label(format!(
+                "{:.1} ms jitter",
+                datapoints.jitter().as_secs_f64() * 1000.
+            ));
+            jitter_sparkline.update(datapoints);
+            ui.label(format!(
+                "{:.1} ms recent jitter",
+                jitter_sparkline.jitter_ms()
+            ));
+            let deltas = jitter_sparkline
+                .deltas
+                .iter()
+                .enumerate()
+                .map(|(i, d)| Value::new(i as f64, *d))
+                .collect::<Vec<_>>();
+            Plot::new("jitter_sparkline")
+                .view_aspect(3.0)
+                .show_axes([false, false])
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(Values::from_values(deltas)).color(line_color));
+                });
+            if let Some(bufferbloat) = datapoints.bufferbloat_ms() {
+                ui.label(format!(
+                    "{:.1} ms more latency under load",
+                    bufferbloat.as_secs_f64() * 1000.
+                ));
+            }
+            ui.label(format!(
+                "{:.1} ms std. deviation",
+                datapoints.latency_stddev().as_secs_f64() * 1000.
+            ));
+            ui.label(format!(
+                "{:.1} ms DNS lookup",
+                datapoints.mean_dns_lookup().as_secs_f64() * 1000.
+            ));
             ui.label(format!("{} timeouts", datapoints.timeouts()));
             ui.label(format!(
                 "{:.1} % timeout ",
                 datapoints.timeouts_for_session() * 100.
             ));
+            ui.label(format!(
+                "{:.2} % available",
+                datapoints.availability() * 100.
+            ));
 
             ui.with_layout(egui::Layout::bottom_up(egui::Align::Center), |ui| {
                 ui.add(egui::Hyperlink::new("https://github.com/woelper/linetest/"));
@@ -149,85 +557,170 @@ impl epi::App for LinetestApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             // The central panel the region left after adding TopPanel's and SidePanel's
 
-            let mut ping_values = vec![];
-            let mut dl_values = vec![];
+            let mut ping_values: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+            let mut ul_values = vec![];
+            let mut dl_values_per_url: BTreeMap<String, Vec<Value>> = BTreeMap::new();
             let mut timeout_values = vec![];
+            let mut error_values = vec![];
+            let mut warmup_ping_values = vec![];
+            let mut warmup_dl_values = vec![];
 
-            let first_instant: SystemTime = match datapoints.first() {
-                Some(dp) => match dp {
-                    Datapoint::Latency(_, ms)
-                    | Datapoint::ThroughputDown(_, ms)
-                    | Datapoint::ThroughputUp(_, ms) => *ms,
-                },
-                None => UNIX_EPOCH,
-            };
+            let first_instant = datapoints
+                .first()
+                .map(Datapoint::timestamp)
+                .unwrap_or(UNIX_EPOCH);
 
-            for dp in &mut *datapoints {
-                match dp {
-                    Datapoint::Latency(maybe_ms, t) =>
+            // long sessions are thinned out so plotting stays fast
+            let downsampled = datapoints.downsample(MAX_PLOT_POINTS);
+            for target in downsampled.ping_targets() {
+                let mut values = vec![];
+                for (t, maybe_ms) in downsampled.for_target(&target).latencies() {
+                    let x = t
+                        .duration_since(first_instant)
+                        .unwrap_or_default()
+                        .as_secs_f64();
                     // check if this is a timeout
-                    {
-                        match maybe_ms {
-                            Some(ms) => ping_values.push(Value::new(
-                                t.duration_since(first_instant)
-                                    .expect("can't set duration")
-                                    .as_secs_f64(),
-                                ms.as_secs_f64() * 1000.,
+                    match maybe_ms {
+                        Some(ms) => values.push(Value::new(x, ms.as_secs_f64() * 1000.)),
+                        None => {
+                            // mark as timeout
+                            timeout_values.push(Value::new(x, 4.0));
+                            // also set to a value
+                            values.push(Value::new(x, 0.01))
+                        }
+                    }
+                }
+                ping_values.insert(target, values);
+            }
+            let dl_values = downsampled
+                .downloads()
+                .map(|(t, d)| {
+                    Value::new(
+                        t.duration_since(first_instant)
+                            .unwrap_or_default()
+                            .as_secs_f64(),
+                        unit.convert(d.unwrap_or_default()),
+                    )
+                })
+                .collect::<Vec<_>>();
+            for dp in &downsampled {
+                match dp {
+                    // plotted above
+                    Datapoint::Latency(..) | Datapoint::ThroughputDown(..) => (),
+                    // failed pings are marked separately from timeouts
+                    Datapoint::LatencyError(_, _, t) => error_values.push(Value::new(
+                        t.duration_since(first_instant)
+                            .unwrap_or_default()
+                            .as_secs_f64(),
+                        4.0,
+                    )),
+                    // DNS lookups, data volume and networks are not plotted
+                    Datapoint::DnsLookup(_, _, _)
+                    | Datapoint::BytesDownloaded(_, _)
+                    | Datapoint::Network(_, _) => (),
+                    // the warm-up is greyed out, as it doesn't count
+                    Datapoint::Warmup(dp) => {
+                        let x = dp
+                            .timestamp()
+                            .duration_since(first_instant)
+                            .unwrap_or_default()
+                            .as_secs_f64();
+                        match dp.as_ref() {
+                            Datapoint::Latency(_, l, _, _) => warmup_ping_values.push(Value::new(
+                                x,
+                                l.map(|l| l.as_secs_f64() * 1000.).unwrap_or(0.01),
                             )),
-                            None => {
-                                // mark as timeout
-                                timeout_values.push(Value::new(
-                                    t.duration_since(first_instant)
-                                        .expect("can't set duration")
-                                        .as_secs_f64(),
-                                    4.0,
-                                ));
-                                // also set to a value
-                                ping_values.push(Value::new(
-                                    t.duration_since(first_instant)
-                                        .expect("can't set duration")
-                                        .as_secs_f64(),
-                                    0.01,
-                                ))
-                            }
+                            Datapoint::ThroughputDown(d, _) => warmup_dl_values
+                                .push(Value::new(x, unit.convert(d.unwrap_or_default()))),
+                            _ => (),
                         }
                     }
-                    Datapoint::ThroughputUp(_, _) => todo!(),
-                    Datapoint::ThroughputDown(d, t) => dl_values.push(Value::new(
+                    Datapoint::ThroughputUp(u, t) => ul_values.push(Value::new(
                         t.duration_since(first_instant)
-                            .expect("can't set duration")
+                            .unwrap_or_default()
                             .as_secs_f64(),
-                        d.unwrap_or_default(),
+                        unit.convert(u.unwrap_or_default()),
                     )),
+                    Datapoint::ThroughputDownPerUrl(url, d, t, _) => dl_values_per_url
+                        .entry(url.clone())
+                        .or_default()
+                        .push(Value::new(
+                            t.duration_since(first_instant)
+                                .unwrap_or_default()
+                                .as_secs_f64(),
+                            unit.convert(d.unwrap_or_default()),
+                        )),
                 }
             }
 
             // let line_color = ui.style().visuals.hyperlink_color;
             ui.label("Latency (ms)");
-            let latency_line = Line::new(Values::from_values(ping_values.clone()))
-                .color(line_color)
-                .name("Ping (ms)")
-                .fill(0.0);
-            let latency_points = Points::new(Values::from_values(ping_values))
-                .stems(0.0)
-                .color(line_color);
             let timeouts = Points::new(Values::from_values(timeout_values))
                 .filled(true)
                 .radius(8.)
                 .name("timeout")
                 .shape(egui::plot::MarkerShape::Down);
+            let errors = Points::new(Values::from_values(error_values))
+                .filled(true)
+                .radius(8.)
+                .color(Color32::RED)
+                .name("error")
+                .shape(egui::plot::MarkerShape::Cross);
+            let warmup_pings = Points::new(Values::from_values(warmup_ping_values))
+                .color(Color32::GRAY)
+                .name("warm-up")
+                .stems(0.0);
 
             Plot::new("latency")
+                .x_axis_formatter(time_axis_formatter(first_instant, *clock_time_axis))
                 .view_aspect(5.0)
                 .legend(Legend::default().text_style(TextStyle::Small))
                 .show(ui, |plot_ui| {
-                    plot_ui.points(latency_points);
+                    for (i, (target, values)) in ping_values.into_iter().enumerate() {
+                        // the first target keeps the main color
+                        let color = if i == 0 {
+                            line_color
+                        } else {
+                            TARGET_COLORS[(i - 1) % TARGET_COLORS.len()]
+                        };
+                        let latency_line = Line::new(Values::from_values(values.clone()))
+                            .color(color)
+                            .name(format!("Ping {} (ms)", target))
+                            .fill(0.0);
+                        let latency_points = Points::new(Values::from_values(values))
+                            .color(color)
+                            .name(format!("Ping {} (ms)", target))
+                            .stems(0.0);
+                        plot_ui.points(latency_points);
 
-                    // add a line to the plot if it is not dense
-                    if datapoints.len() < 100 {
-                        plot_ui.line(latency_line);
+                        // add a line to the plot if it is not dense
+                        if datapoints.len() < 100 {
+                            plot_ui.line(latency_line);
+                        }
+                    }
+                    if *smoothing_window > 1 {
+                        for target in datapoints.ping_targets() {
+                            let smoothed = datapoints
+                                .for_target(&target)
+                                .latency_moving_average(*smoothing_window)
+                                .into_iter()
+                                .map(|(t, l)| {
+                                    Value::new(
+                                        t.duration_since(first_instant)
+                                            .unwrap_or_default()
+                                            .as_secs_f64(),
+                                        l.as_secs_f64() * 1000.,
+                                    )
+                                })
+                                .collect::<Vec<_>>();
+                            plot_ui.line(Line::new(Values::from_values(smoothed)).width(2.0).name(
+                                format!("Ping {} (average of {})", target, smoothing_window),
+                            ));
+                        }
                     }
                     plot_ui.points(timeouts);
+                    plot_ui.points(errors);
+                    plot_ui.points(warmup_pings);
                     plot_ui.hline(
                         HLine::new(datapoints.mean_latency().as_millis() as f64)
                             .name(format!(
@@ -236,37 +729,249 @@ impl epi::App for LinetestApp {
                             ))
                             .color(line_color.linear_multiply(0.1)),
                     );
+                    if let Some(p95) = datapoints.latency_p95() {
+                        plot_ui.hline(
+                            HLine::new(p95.as_secs_f64() * 1000.)
+                                .name(format!("95th percentile latency ({}ms)", p95.as_millis()))
+                                .color(line_color.linear_multiply(0.4)),
+                        );
+                    }
                 });
 
-            ui.label("Download speed (Mbit/s)");
+            ui.label(format!("Download speed ({})", unit));
             let download_line = Line::new(Values::from_values(dl_values))
                 .color(line_color)
+                .name("Combined")
                 .fill(0.0);
-            Plot::new("dl").view_aspect(4.0).show(ui, |plot_ui| {
-                plot_ui.line(download_line);
-            });
+            Plot::new("dl")
+                .x_axis_formatter(time_axis_formatter(first_instant, *clock_time_axis))
+                .view_aspect(4.0)
+                .legend(Legend::default().text_style(TextStyle::Small))
+                .show(ui, |plot_ui| {
+                    plot_ui.line(download_line);
+                    plot_ui.points(
+                        Points::new(Values::from_values(warmup_dl_values))
+                            .color(Color32::GRAY)
+                            .name("warm-up"),
+                    );
+                    for (url, values) in dl_values_per_url {
+                        plot_ui.line(Line::new(Values::from_values(values)).name(url));
+                    }
+                });
 
-            if receiver.is_none() {
-                if ui.button("⏺ Start recording").clicked() {
+            if !ul_values.is_empty() {
+                ui.label(format!("Upload speed ({})", unit));
+                let upload_line = Line::new(Values::from_values(ul_values))
+                    .color(line_color)
+                    .name("Upload")
+                    .fill(0.0);
+                Plot::new("ul")
+                    .x_axis_formatter(time_axis_formatter(first_instant, *clock_time_axis))
+                    .view_aspect(4.0)
+                    .legend(Legend::default().text_style(TextStyle::Small))
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(upload_line);
+                    });
+            }
+
+            ui.label("Packet loss per minute (%)");
+            let loss_bars = datapoints
+                .timeouts_by_bucket(LOSS_BUCKET)
+                .into_iter()
+                // leave gaps where nothing was recorded
+                .filter(|(_, _, total)| *total > 0)
+                .map(|(start, timeouts, total)| {
+                    Bar::new(
+                        start
+                            .duration_since(first_instant)
+                            .unwrap_or_default()
+                            .as_secs_f64()
+                            + LOSS_BUCKET.as_secs_f64() / 2.,
+                        timeouts as f64 / total as f64 * 100.,
+                    )
+                    .width(LOSS_BUCKET.as_secs_f64())
+                })
+                .collect::<Vec<_>>();
+            Plot::new("loss")
+                .x_axis_formatter(time_axis_formatter(first_instant, *clock_time_axis))
+                .view_aspect(8.0)
+                .include_y(0.0)
+                .show(ui, |plot_ui| {
+                    plot_ui.bar_chart(
+                        BarChart::new(loss_bars)
+                            .color(Color32::RED)
+                            .name("Packet loss"),
+                    );
+                });
+
+            let histogram = datapoints.latency_histogram(HISTOGRAM_BUCKET_MS);
+            if !histogram.is_empty() {
+                ui.label("Latency distribution (pings per 10 ms)");
+                let histogram_bars = histogram
+                    .into_iter()
+                    .map(|(lower, count)| {
+                        Bar::new(lower + HISTOGRAM_BUCKET_MS / 2., count as f64)
+                            .width(HISTOGRAM_BUCKET_MS)
+                    })
+                    .collect::<Vec<_>>();
+                Plot::new("histogram")
+                    .view_aspect(8.0)
+                    .include_y(0.0)
+                    .show(ui, |plot_ui| {
+                        plot_ui.bar_chart(
+                            BarChart::new(histogram_bars)
+                                .color(line_color)
+                                .name("Pings"),
+                        );
+                    });
+            }
+
+            if let Some(e) = start_error {
+                ui.colored_label(Color32::RED, e.as_str());
+            }
+            let mut stop = false;
+            ui.horizontal(|ui| {
+                if let Some(valid_receiver) = receiver {
+                    if ui.button("⏸ Pause").clicked() {
+                        // keep what has already been measured
+                        for dp in valid_receiver.try_iter() {
+                            append_to_log(measurement, &dp, log_error, log_failed);
+                            measurement.push_retained(datapoints, dp);
+                        }
+                        *receiver = None;
+                        *paused = true;
+                        *paused_remaining = recording_deadline
+                            .take()
+                            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    }
+                    stop = ui.button("⏹ Stop").clicked();
+                    if let Some(deadline) = recording_deadline {
+                        match deadline.checked_duration_since(Instant::now()) {
+                            Some(left) => ui.label(format!("{} left", format_countdown(left))),
+                            // the last cycle is still measured
+                            None => ui.label("Finishing..."),
+                        };
+                    }
+                    return;
+                }
+
+                let label = if *paused {
+                    "▶ Resume"
+                } else {
+                    "⏺ Start recording"
+                };
+                if ui.button(label).clicked() {
                     //measurement.logfile = MeasurementBuilder::default().logfile;
 
-                    *datapoints = vec![];
-                    if let Ok(new_rec) = measurement.run_until_receiver_drops() {
-                        *receiver = Some(new_rec);
+                    // a resumed recording only runs for the time it had left
+                    let duration = if *paused {
+                        *paused_remaining
+                    } else {
+                        *record_for
+                    };
+                    // report an unwritable data directory before anything is measured
+                    *log_error = measurement
+                        .check_writable()
+                        .err()
+                        .map(|e| format!("{:#}", e));
+                    *log_failed = log_error.is_some();
+                    // validation resolves the ping targets, so only do it when starting
+                    let started = measurement.validate().and_then(|_| match duration {
+                        Some(duration) => measurement.run_until_duration(duration),
+                        None => measurement.run_until_receiver_drops(),
+                    });
+                    match started {
+                        Ok(new_rec) => {
+                            // a resumed recording continues the session and its logfile
+                            if !*paused {
+                                *datapoints = vec![];
+                                *log_metadata = None;
+                                // its results would predate the new session
+                                *quick_test = None;
+                            }
+                            *replay = None;
+                            *receiver = Some(new_rec);
+                            *recording_deadline =
+                                duration.map(|duration| Instant::now() + duration);
+                            *start_error = None;
+                            *paused = false;
+                        }
+                        Err(e) => *start_error = Some(e.to_string()),
                     }
                 }
-            } else if ui.button("⏹ Stop").clicked() {
+                if *paused {
+                    stop = ui.button("⏹ Stop").clicked();
+                }
+            });
+            ui.horizontal(|ui| {
+                if quick_test.is_some() {
+                    ui.add(egui::Spinner::new());
+                    ui.label("Testing...");
+                } else if ui.button("⏱ Quick test").clicked() {
+                    // pinging and downloading block, so keep them off the ui thread
+                    let measurement = measurement.clone();
+                    let (sender, quick_receiver) = channel();
+                    thread::spawn(move || {
+                        let result = measurement
+                            .validate()
+                            .and_then(|_| measurement.run_once())
+                            .map_err(|e| e.to_string());
+                        let _ = sender.send(result);
+                    });
+                    *quick_test = Some(quick_receiver);
+                }
+            });
+            // a recording for a set time ends by itself, like it was stopped
+            if let Some(valid_receiver) = receiver.as_ref().filter(|r| !r.is_running()) {
+                for dp in valid_receiver.try_iter() {
+                    append_to_log(measurement, &dp, log_error, log_failed);
+                    measurement.push_retained(datapoints, dp);
+                }
+                stop = true;
+            }
+            if stop {
                 *receiver = None;
+                *paused = false;
+                *recording_deadline = None;
+                *paused_remaining = None;
 
                 //refresh logs on disk after last session finishes
-                if let Ok(new_logs) = MeasurementBuilder::get_logs() {
+                if let Ok(new_logs) = measurement.logs() {
                     *logs = new_logs;
                 }
                 // generate new log name so we don't overwrite the last
-                measurement.logfile = MeasurementBuilder::default().logfile;
+                if measurement.logfile.is_some() {
+                    measurement.logfile = Some(measurement.new_logfile());
+                }
             }
 
             egui::CollapsingHeader::new("Settings").show(ui, |ui| {
+                let mut memory_only = measurement.logfile.is_none();
+                if ui
+                    .add_enabled(
+                        receiver.is_none() && !*paused,
+                        egui::Checkbox::new(&mut memory_only, "Don't save"),
+                    )
+                    .changed()
+                {
+                    measurement.logfile = if memory_only {
+                        None
+                    } else {
+                        Some(measurement.new_logfile())
+                    };
+                }
+                ui.horizontal(|ui| {
+                    let mut limited = record_for.is_some();
+                    let mut minutes = record_for.map(|d| d.as_secs() / 60).unwrap_or(10);
+                    ui.checkbox(&mut limited, "Record for");
+                    ui.add_enabled(
+                        limited,
+                        egui::DragValue::new(&mut minutes)
+                            .clamp_range(1..=7 * 24 * 60)
+                            .suffix(" min"),
+                    );
+                    *record_for = limited.then_some(Duration::from_secs(minutes * 60));
+                });
                 if let Some(log) = measurement.logfile.as_mut() {
                     let mut log_file_string = log
                         .file_name()
@@ -275,7 +980,9 @@ impl epi::App for LinetestApp {
                         .to_string();
                     if ui.text_edit_singleline(&mut log_file_string).changed() {
                         if let Some(parent) = log.parent() {
-                            *log = parent.join(log_file_string).with_extension("ltest");
+                            *log = parent
+                                .join(log_file_string)
+                                .with_extension(linetest::LOG_EXTENSION);
                         }
                     }
                 }
@@ -293,9 +1000,129 @@ impl epi::App for LinetestApp {
                     ui.add(egui::DragValue::new(&mut measurement.throughput_ping_ratio));
                     ui.label("Perform speedtest after these many pings");
                 });
+
+                ui.horizontal(|ui| {
+                    let mut limited = measurement.max_retained.is_some();
+                    if ui
+                        .checkbox(&mut limited, "Only show the most recent")
+                        .changed()
+                    {
+                        measurement.max_retained = if limited { Some(10_000) } else { None };
+                    }
+                    if let Some(max) = measurement.max_retained.as_mut() {
+                        ui.add(egui::DragValue::new(max).clamp_range(1..=1_000_000));
+                        ui.label("datapoints");
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let mut warmup = measurement.warmup.as_secs();
+                    ui.label("Leave out the first");
+                    if ui
+                        .add(egui::DragValue::new(&mut warmup).clamp_range(0..=3600))
+                        .changed()
+                    {
+                        measurement.warmup = Duration::from_secs(warmup);
+                    }
+                    ui.label("seconds of the statistics");
+                });
+
+                ui.label("Ping targets");
+                edit_list(ui, &mut measurement.ping_ips, "8.8.8.8");
+
+                ui.label("Download urls");
+                edit_list(ui, &mut measurement.downloads_urls, "https://");
+
+                ui.horizontal(|ui| {
+                    let mut use_iperf3 = measurement.iperf3_server.is_some();
+                    if ui
+                        .checkbox(&mut use_iperf3, "Download from the iperf3 server")
+                        .changed()
+                    {
+                        measurement.iperf3_server = if use_iperf3 {
+                            Some(String::new())
+                        } else {
+                            None
+                        };
+                    }
+                    if let Some(server) = measurement.iperf3_server.as_mut() {
+                        ui.text_edit_singleline(server);
+                    }
+                });
+
+                ui.checkbox(clock_time_axis, "Show the clock time on the time axis");
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(throughput_unit, ThroughputUnit::MbitPerSec, "Mbit/s");
+                    ui.radio_value(throughput_unit, ThroughputUnit::MBytePerSec, "MB/s");
+                    ui.label("Speed unit");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(smoothing_window, 1..=50));
+                    ui.label("Latency samples to average for the smoothed line");
+                });
+
+                ui.checkbox(
+                    &mut measurement.per_url_throughput,
+                    "Record the speed of each download url",
+                );
+
+                ui.checkbox(
+                    &mut measurement.bufferbloat,
+                    "Measure the latency during speed tests (bufferbloat)",
+                );
+
+                ui.checkbox(
+                    &mut measurement.detect_isp,
+                    "Store the provider and location of the connection (asks ipinfo.io)",
+                );
+
+                ui.checkbox(
+                    &mut measurement.warm_connection,
+                    "Set up connections before timing downloads",
+                );
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut measurement.combined_timing,
+                        CombinedTiming::WallClock,
+                        "Whole test",
+                    );
+                    ui.radio_value(
+                        &mut measurement.combined_timing,
+                        CombinedTiming::LongestTransfer,
+                        "Longest download",
+                    );
+                    ui.label("Time the combined speed by");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut measurement.address_family, AddressFamily::Auto, "Auto");
+                    ui.radio_value(&mut measurement.address_family, AddressFamily::V4, "IPv4");
+                    ui.radio_value(&mut measurement.address_family, AddressFamily::V6, "IPv6");
+                    ui.label("IP version");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut measurement.ping_backend,
+                        PingBackend::System,
+                        "System ping",
+                    );
+                    ui.radio_value(
+                        &mut measurement.ping_backend,
+                        PingBackend::Icmp,
+                        "Built-in ICMP",
+                    );
+                    ui.label("Ping");
+                });
             });
 
             egui::CollapsingHeader::new("Log archive").show(ui, |ui| {
+                ui.label(
+                    "Drop a log or the output of ping or a speedtest onto the window to open it",
+                );
                 if egui::ComboBox::from_label(if receiver.is_some() {
                     "Stop and load selected log"
                 } else {
@@ -312,14 +1139,53 @@ impl epi::App for LinetestApp {
                 .changed()
                 {
                     *receiver = None;
+                    *replay = None;
                     datapoints.clear();
+                    *log_metadata = None;
                     if let Some(log) = logs.get(*log_index) {
-                        datapoints.load(log).unwrap();
-                        info!("Loaded {} data points", datapoints.len());
+                        // e.g. a log of a newer version or a truncated one
+                        match datapoints.load(log) {
+                            Ok(()) => {
+                                *log_metadata = linetest::load_metadata(log).unwrap_or_default();
+                                *open_error = None;
+                                info!("Loaded {} data points", datapoints.len());
+                            }
+                            Err(e) => {
+                                datapoints.clear();
+                                *open_error = Some(format!("Can't read {}: {}", log.display(), e));
+                            }
+                        }
                     }
                 }
 
                 if let Some(log) = logs.get(*log_index) {
+                    ui.horizontal(|ui| {
+                        if replay.is_some() {
+                            if ui.button("⏹ Stop replay").clicked() {
+                                *replay = None;
+                            }
+                        } else if ui.button("▶ Replay this log").clicked() {
+                            match MeasurementBuilder::replay(log, *replay_speed) {
+                                Ok(new_replay) => {
+                                    *receiver = None;
+                                    *paused = false;
+                                    datapoints.clear();
+                                    *log_metadata =
+                                        linetest::load_metadata(log).unwrap_or_default();
+                                    *replay = Some(new_replay);
+                                    *open_error = None;
+                                }
+                                Err(e) => *open_error = Some(e.to_string()),
+                            }
+                        }
+                        ui.add(
+                            egui::DragValue::new(replay_speed)
+                                .clamp_range(0.1..=1000.0)
+                                .speed(0.1)
+                                .suffix("x"),
+                        );
+                        ui.label("speed");
+                    });
                     if ui
                         .button(format!(
                             "Delete {}",
@@ -329,13 +1195,261 @@ impl epi::App for LinetestApp {
                         ))
                         .clicked()
                     {
-                        let _ = std::fs::remove_file(&log);
-                        if let Ok(new_logs) = MeasurementBuilder::get_logs() {
+                        let _ = std::fs::remove_file(log);
+                        if let Ok(new_logs) = measurement.logs() {
                             *logs = new_logs;
                         }
                     }
                 }
+
+                ui.separator();
+                ui.label("Select logs to merge");
+                for log in logs.iter() {
+                    let mut selected = merge_selection.contains(log);
+                    let name = log
+                        .file_name()
+                        .unwrap_or(OsStr::new("no_file_name"))
+                        .to_string_lossy()
+                        .to_string();
+                    if ui.checkbox(&mut selected, name).changed() {
+                        if selected {
+                            merge_selection.push(log.clone());
+                        } else {
+                            merge_selection.retain(|l| l != log);
+                        }
+                    }
+                }
+                if ui
+                    .add_enabled(
+                        merge_selection.len() > 1,
+                        egui::Button::new("Merge selected"),
+                    )
+                    .clicked()
+                {
+                    match linetest::merge(merge_selection) {
+                        Ok(merged) => {
+                            *receiver = None;
+                            *replay = None;
+                            *datapoints = merged;
+                            *log_metadata = None;
+                            info!("Merged {} data points", datapoints.len());
+                        }
+                        Err(e) => error!("Can't merge logs: {}", e),
+                    }
+                }
+
+                ui.separator();
+                ui.label("Compare two logs");
+                let log_name = |i: usize| {
+                    logs.get(i)
+                        .and_then(|log| log.file_name())
+                        .unwrap_or(OsStr::new("no_file_name"))
+                        .to_string_lossy()
+                        .to_string()
+                };
+                egui::ComboBox::from_label("Before").show_index(
+                    ui,
+                    &mut compare_indices.0,
+                    logs.len(),
+                    log_name,
+                );
+                egui::ComboBox::from_label("After").show_index(
+                    ui,
+                    &mut compare_indices.1,
+                    logs.len(),
+                    log_name,
+                );
+                if ui
+                    .add_enabled(logs.len() > 1, egui::Button::new("Compare"))
+                    .clicked()
+                {
+                    let mut before = vec![];
+                    let mut after = vec![];
+                    let loaded = logs
+                        .get(compare_indices.0)
+                        .zip(logs.get(compare_indices.1))
+                        .ok_or_else(|| "No log selected".to_string())
+                        .and_then(|(a, b)| {
+                            before
+                                .load(a)
+                                .and_then(|_| after.load(b))
+                                .map_err(|e| e.to_string())
+                        });
+                    match loaded {
+                        Ok(_) => *comparison = Some((before, after)),
+                        Err(e) => error!("Can't load logs to compare: {}", e),
+                    }
+                }
             });
+
+            if let Some(sessions) = comparison {
+                let mut close = false;
+                egui::CollapsingHeader::new("Comparison")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        comparison_view(ui, sessions, line_color, *throughput_unit);
+                        close = ui.button("Close comparison").clicked();
+                    });
+                if close {
+                    *comparison = None;
+                }
+            }
+        });
+    }
+}
+
+/// The latency of two sessions overlaid, each relative to its own start, and a table
+/// of how the aggregate statistics changed from the first session to the second
+fn comparison_view(
+    ui: &mut egui::Ui,
+    (before, after): &(Vec<Datapoint>, Vec<Datapoint>),
+    line_color: Color32,
+    unit: ThroughputUnit,
+) {
+    let relative_latencies = |datapoints: &Vec<Datapoint>| {
+        let first_instant = datapoints
+            .first()
+            .map(Datapoint::timestamp)
+            .unwrap_or(UNIX_EPOCH);
+        datapoints
+            .downsample(MAX_PLOT_POINTS)
+            .latencies()
+            .filter_map(|(t, latency)| {
+                Some(Value::new(
+                    t.duration_since(first_instant).ok()?.as_secs_f64(),
+                    latency?.as_secs_f64() * 1000.,
+                ))
+            })
+            .collect::<Vec<_>>()
+    };
+    ui.label("Latency (ms) since the start of each session (s)");
+    Plot::new("comparison")
+        .view_aspect(5.0)
+        .legend(Legend::default().text_style(TextStyle::Small))
+        .show(ui, |plot_ui| {
+            plot_ui.line(
+                Line::new(Values::from_values(relative_latencies(before)))
+                    .color(line_color)
+                    .name("Before"),
+            );
+            plot_ui.line(
+                Line::new(Values::from_values(relative_latencies(after)))
+                    .color(TARGET_COLORS[0])
+                    .name("After"),
+            );
         });
+
+    let delta = before.compare(after);
+    egui::Grid::new("comparison_table")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("");
+            ui.label("Before");
+            ui.label("After");
+            ui.label("Change");
+            ui.end_row();
+            let ms = |d: Duration| d.as_secs_f64() * 1000.;
+            let download = format!("Download ({})", unit);
+            let rows = [
+                (
+                    "Mean latency (ms)",
+                    ms(before.mean_latency()),
+                    ms(after.mean_latency()),
+                    delta.mean_latency_ms,
+                ),
+                (
+                    "Jitter (ms)",
+                    ms(before.jitter()),
+                    ms(after.jitter()),
+                    delta.jitter_ms,
+                ),
+                (
+                    download.as_str(),
+                    unit.convert(before.mean_dl()) as f64,
+                    unit.convert(after.mean_dl()) as f64,
+                    unit.convert(delta.download_mbit) as f64,
+                ),
+                (
+                    "Timeouts (%)",
+                    before.timeouts_for_session() as f64 * 100.,
+                    after.timeouts_for_session() as f64 * 100.,
+                    delta.timeout_rate as f64 * 100.,
+                ),
+            ];
+            for (name, before, after, change) in rows {
+                ui.label(name);
+                ui.label(format!("{:.1}", before));
+                ui.label(format!("{:.1}", after));
+                ui.label(format!("{:+.1}", change));
+                ui.end_row();
+            }
+        });
+}
+
+/// Read a log file, e.g. one dropped onto the window, or import the output of another tool
+fn open_log(path: &std::path::Path) -> Result<Vec<Datapoint>, String> {
+    if !linetest::is_log(path) {
+        // maybe the output of another measurement tool
+        return std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| linetest::import(&text).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                format!(
+                    "{} is neither a linetest log (*.{}) nor the output of ping or a speedtest: {}",
+                    path.display(),
+                    linetest::LOG_EXTENSION,
+                    e
+                )
+            });
+    }
+    let mut datapoints = vec![];
+    datapoints
+        .load(path)
+        .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    Ok(datapoints)
+}
+
+/// Editable rows of strings with a button to remove each row and one to add a new row
+fn edit_list(ui: &mut egui::Ui, items: &mut Vec<String>, new_item: &str) {
+    let mut remove = None;
+    for (i, item) in items.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(item);
+            if ui.button("🗑").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        items.remove(i);
+    }
+    if ui.button("➕ Add").clicked() {
+        items.push(new_item.to_string());
+    }
+}
+
+/// Format the time axis of a plot, whose values are seconds since `first_instant`,
+/// either as local clock time or as the plain seconds. Values that are no valid clock time,
+/// e.g. before the start or far beyond it, are shown as seconds as well.
+fn time_axis_formatter(
+    first_instant: SystemTime,
+    clock_time: bool,
+) -> impl Fn(f64, &RangeInclusive<f64>) -> String {
+    let clock = move |secs: f64| {
+        let t = first_instant.checked_add(Duration::try_from_secs_f64(secs).ok()?)?;
+        let since_epoch = t.duration_since(UNIX_EPOCH).ok()?;
+        let t = Local
+            .timestamp_opt(
+                since_epoch.as_secs().try_into().ok()?,
+                since_epoch.subsec_nanos(),
+            )
+            .single()?;
+        Some(t.format("%H:%M:%S").to_string())
+    };
+    move |secs, _range| {
+        if !clock_time {
+            return format!("{}", secs);
+        }
+        clock(secs).unwrap_or_else(|| format!("{}", secs))
     }
 }
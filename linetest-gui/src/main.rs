@@ -7,7 +7,15 @@ fn main() -> Result<(), Error> {
     std::env::set_var("RUST_LOG", "info");
     let _ = env_logger::try_init();
 
-    let app = app::LinetestApp::default();
+    let mut app = app::LinetestApp::default();
+    // a configuration file given as the only argument replaces the default settings
+    if let Some(config) = std::env::args_os().nth(1) {
+        app.measurement =
+            linetest::MeasurementBuilder::from_overrides(&linetest::BuilderOverrides {
+                config: Some(config.into()),
+                ..Default::default()
+            })?;
+    }
 
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(Box::new(app), native_options);
@@ -0,0 +1,128 @@
+use std::{collections::HashMap, time::Duration};
+
+use super::Datapoint;
+
+/// Limits that trigger an [Alert] when a measurement breaches them. Rules that are `None` are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct AlertThresholds {
+    /// Alert if a ping takes longer than this
+    pub max_latency: Option<Duration>,
+    /// Alert if the download speed drops below this, or a download fails
+    pub min_download_mbit: Option<f32>,
+    /// Alert if this many pings in a row to the same target time out
+    pub max_consecutive_timeouts: Option<usize>,
+}
+
+/// A breached threshold, along with the offending value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    /// A ping to a target exceeded `max_latency`
+    Latency(String, Duration),
+    /// The download speed was below `min_download_mbit`. `None` if the download failed.
+    Download(Option<f32>),
+    /// A target did not answer `max_consecutive_timeouts` pings in a row
+    ConsecutiveTimeouts(String, usize),
+}
+
+/// Checks incoming [Datapoint]s against [AlertThresholds]
+#[derive(Debug, Default)]
+pub(crate) struct AlertMonitor {
+    thresholds: AlertThresholds,
+    consecutive_timeouts: HashMap<String, usize>,
+}
+
+impl AlertMonitor {
+    pub(crate) fn new(thresholds: AlertThresholds) -> Self {
+        Self {
+            thresholds,
+            consecutive_timeouts: HashMap::new(),
+        }
+    }
+
+    /// Return an [Alert] if this datapoint breaches a threshold. A run of timeouts
+    /// alerts once when it reaches the limit, not for every timeout after that.
+    pub(crate) fn check(&mut self, dp: &Datapoint) -> Option<Alert> {
+        match dp {
+            Datapoint::Latency(target, Some(latency), ..) => {
+                self.consecutive_timeouts.remove(target);
+                match self.thresholds.max_latency {
+                    Some(max) if *latency > max => Some(Alert::Latency(target.clone(), *latency)),
+                    _ => None,
+                }
+            }
+            Datapoint::Latency(target, None, ..) => {
+                let count = self.consecutive_timeouts.entry(target.clone()).or_default();
+                *count += 1;
+                match self.thresholds.max_consecutive_timeouts {
+                    Some(max) if *count == max => {
+                        Some(Alert::ConsecutiveTimeouts(target.clone(), *count))
+                    }
+                    _ => None,
+                }
+            }
+            Datapoint::ThroughputDown(tp, _) => match (self.thresholds.min_download_mbit, tp) {
+                (Some(min), Some(tp)) if *tp < min => Some(Alert::Download(Some(*tp))),
+                (Some(_), None) => Some(Alert::Download(None)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let mut monitor = AlertMonitor::new(AlertThresholds {
+            max_latency: Some(Duration::from_millis(100)),
+            min_download_mbit: Some(10.),
+            max_consecutive_timeouts: Some(2),
+        });
+        let slow = Duration::from_millis(150);
+        assert_eq!(
+            monitor.check(&Datapoint::add_latency(
+                "a",
+                Some(Duration::from_millis(20))
+            )),
+            None
+        );
+        assert_eq!(
+            monitor.check(&Datapoint::add_latency("a", Some(slow))),
+            Some(Alert::Latency("a".to_string(), slow))
+        );
+        assert_eq!(monitor.check(&Datapoint::add_tp_down(Some(50.))), None);
+        assert_eq!(
+            monitor.check(&Datapoint::add_tp_down(Some(5.))),
+            Some(Alert::Download(Some(5.)))
+        );
+        assert_eq!(
+            monitor.check(&Datapoint::add_tp_down(None)),
+            Some(Alert::Download(None))
+        );
+    }
+
+    #[test]
+    fn consecutive_timeouts() {
+        let mut monitor = AlertMonitor::new(AlertThresholds {
+            max_consecutive_timeouts: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(monitor.check(&Datapoint::add_latency("a", None)), None);
+        // timeouts are counted per target
+        assert_eq!(monitor.check(&Datapoint::add_latency("b", None)), None);
+        assert_eq!(
+            monitor.check(&Datapoint::add_latency("a", None)),
+            Some(Alert::ConsecutiveTimeouts("a".to_string(), 2))
+        );
+        assert_eq!(monitor.check(&Datapoint::add_latency("a", None)), None);
+        // a reply ends the run
+        monitor.check(&Datapoint::add_latency(
+            "a",
+            Some(Duration::from_millis(20)),
+        ));
+        assert_eq!(monitor.check(&Datapoint::add_latency("a", None)), None);
+    }
+}
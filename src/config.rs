@@ -0,0 +1,211 @@
+use anyhow::{Context, Error};
+use serde::{Deserialize, Deserializer, Serializer};
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{CombinedTiming, MeasurementBuilder};
+
+/// Settings given to a front-end, e.g. on the command line. They take precedence over
+/// the configuration file, which takes precedence over the defaults. Fields that are
+/// `None`, `false` or empty keep the configured value.
+#[derive(Debug, Clone, Default)]
+pub struct BuilderOverrides {
+    /// A TOML file with the settings to start from, see [MeasurementBuilder::from_config]
+    pub config: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub download_urls: Vec<String>,
+    pub ping_delay: Option<Duration>,
+    pub proxy: Option<String>,
+    pub iperf3_server: Option<String>,
+    pub throughput_ping_ratio: Option<usize>,
+    /// Download each url over this many connections, see [MeasurementBuilder::connections]
+    pub connections: Option<usize>,
+    /// How the combined download speed is timed, see [MeasurementBuilder::combined_timing]
+    pub combined_timing: Option<CombinedTiming>,
+    pub webhook_url: Option<String>,
+    pub bufferbloat: bool,
+    pub warm_connection: bool,
+    /// Don't download or upload, see [MeasurementBuilder::latency_only]
+    pub latency_only: bool,
+    /// Keep the measurement in memory only, see [MeasurementBuilder::without_log]
+    pub no_log: bool,
+    pub max_retained: Option<usize>,
+    /// Keep only this many datapoints in a single log, see [MeasurementBuilder::flight_recorder]
+    pub flight_recorder: Option<usize>,
+    pub warmup: Option<Duration>,
+    pub label: Option<String>,
+    pub detect_isp: bool,
+}
+
+impl MeasurementBuilder {
+    /// Start from the configuration file of `overrides`, or the defaults without one,
+    /// and apply the other `overrides` on top
+    pub fn from_overrides(overrides: &BuilderOverrides) -> Result<Self, Error> {
+        let measurement = match &overrides.config {
+            Some(config) => Self::from_config(config)
+                .with_context(|| format!("Can't load {}", config.display()))?,
+            None => Self::default(),
+        };
+        Ok(measurement.with_overrides(overrides))
+    }
+
+    /// Apply `overrides` to these settings
+    pub fn with_overrides(&self, overrides: &BuilderOverrides) -> Self {
+        let mut measurement = self.to_owned();
+        if let Some(dir) = &overrides.data_dir {
+            measurement = measurement.with_data_dir(dir);
+        }
+        if !overrides.download_urls.is_empty() {
+            measurement.downloads_urls = overrides.download_urls.clone();
+        }
+        if let Some(delay) = overrides.ping_delay {
+            measurement.ping_delay = delay;
+        }
+        if let Some(proxy) = &overrides.proxy {
+            measurement.proxy = Some(proxy.clone());
+        }
+        if let Some(server) = &overrides.iperf3_server {
+            measurement = measurement.with_iperf3(server);
+        }
+        if let Some(n) = overrides.throughput_ping_ratio {
+            measurement = measurement.with_throughput_ping_ratio(n);
+        }
+        if let Some(connections) = overrides.connections {
+            measurement.connections = connections;
+        }
+        if let Some(timing) = overrides.combined_timing {
+            measurement = measurement.with_combined_timing(timing);
+        }
+        if let Some(url) = &overrides.webhook_url {
+            measurement.webhook_url = Some(url.clone());
+        }
+        measurement.bufferbloat |= overrides.bufferbloat;
+        measurement.warm_connection |= overrides.warm_connection;
+        measurement.detect_isp |= overrides.detect_isp;
+        if overrides.latency_only {
+            measurement = measurement.latency_only();
+        }
+        if overrides.no_log {
+            measurement = measurement.without_log();
+        }
+        if let Some(max) = overrides.max_retained {
+            measurement = measurement.with_max_retained(max);
+        }
+        if let Some(capacity) = overrides.flight_recorder {
+            measurement = measurement.flight_recorder(capacity);
+        }
+        if let Some(warmup) = overrides.warmup {
+            measurement = measurement.with_warmup(warmup);
+        }
+        if let Some(label) = &overrides.label {
+            measurement.label = Some(label.clone());
+        }
+        measurement
+    }
+
+    /// Load settings from a TOML file. Settings missing from the file keep their default value.
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let measurement: Self = toml::from_str(&read_to_string(path.as_ref())?)?;
+        // the logfile is not part of the configuration, but belongs into the configured data dir
+        Ok(Self {
+            logfile: Some(measurement.new_logfile()),
+            ..measurement
+        })
+    }
+
+    /// Save the current settings to a TOML file
+    pub fn save_config<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        if let Some(parent) = path.as_ref().parent() {
+            if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                create_dir_all(parent)?;
+            }
+        }
+        write(path.as_ref(), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// (De)serialize a [Duration] as a plain number of seconds
+pub(crate) mod duration_secs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        if duration.subsec_nanos() == 0 {
+            serializer.serialize_u64(duration.as_secs())
+        } else {
+            serializer.serialize_f64(duration.as_secs_f64())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        if !secs.is_finite() || secs < 0.0 {
+            return Err(serde::de::Error::custom(
+                "duration must be a positive number of seconds",
+            ));
+        }
+        Ok(Duration::from_secs_f64(secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_config() {
+        let measurement: MeasurementBuilder = toml::from_str(
+            r#"
+            ping_ips = ["1.1.1.1", "192.168.0.1"]
+            ping_delay = 2
+            download_timeout = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(measurement.ping_ips, vec!["1.1.1.1", "192.168.0.1"]);
+        assert_eq!(measurement.ping_delay, Duration::from_secs(2));
+        assert_eq!(measurement.download_timeout, Duration::from_millis(500));
+        assert_eq!(
+            measurement.downloads_urls,
+            MeasurementBuilder::default().downloads_urls
+        );
+    }
+
+    #[test]
+    fn config_roundtrip() {
+        let path = std::env::temp_dir().join("linetest_config_roundtrip.toml");
+        let measurement = MeasurementBuilder::default().with_ping_delay(3);
+        measurement.save_config(&path).unwrap();
+        let loaded = MeasurementBuilder::from_config(&path).unwrap();
+        assert_eq!(loaded.ping_delay, Duration::from_secs(3));
+        assert_eq!(loaded.downloads_urls, measurement.downloads_urls);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn overrides_precedence() {
+        let path = std::env::temp_dir().join("linetest_overrides_precedence.toml");
+        MeasurementBuilder::default()
+            .with_ping_delay(3)
+            .with_throughput_ping_ratio(5)
+            .save_config(&path)
+            .unwrap();
+        let overrides = BuilderOverrides {
+            config: Some(path.clone()),
+            ping_delay: Some(Duration::from_secs(7)),
+            combined_timing: Some(CombinedTiming::LongestTransfer),
+            ..Default::default()
+        };
+        let measurement = MeasurementBuilder::from_overrides(&overrides).unwrap();
+        // the flag beats the configuration file, which beats the default
+        assert_eq!(measurement.ping_delay, Duration::from_secs(7));
+        assert_eq!(measurement.throughput_ping_ratio, 5);
+        assert_eq!(measurement.combined_timing, CombinedTiming::LongestTransfer);
+        assert!(!measurement.bufferbloat);
+        let _ = std::fs::remove_file(&path);
+        assert!(MeasurementBuilder::from_overrides(&overrides).is_err());
+    }
+}
@@ -1,145 +1,2293 @@
-use anyhow::Error;
+use anyhow::{anyhow, Context, Error};
 
 use std::{
-    fs::{create_dir_all, File},
-    path::{Path},
-    time::{Duration},
+    collections::{BTreeMap, VecDeque},
+    convert::TryFrom,
+    fs::{create_dir_all, remove_file, rename, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use super::{MeasurementResult, Datapoint};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
 
-/// A couple of analyis methods on a [MeasurementResult]
+use super::{
+    is_binary_log, is_compressed_log, Datapoint, LogCapacity, MeasurementResult, Metadata,
+    UNKNOWN_NETWORK,
+};
+
+/// A couple of analyis methods on a [MeasurementResult]. Measurements of the warm-up,
+/// see [Datapoint::Warmup], are left out.
 pub trait Evaluation {
+    /// The time and latency of every ping, `None` for timeouts
+    fn latencies(&self) -> impl Iterator<Item = (SystemTime, Option<Duration>)> + '_;
+
+    /// The time and combined speed in Mbit/s of every download test, `None` if it failed
+    fn downloads(&self) -> impl Iterator<Item = (SystemTime, Option<f32>)> + '_;
+
     /// Mean download speed for a measurement
     fn mean_dl(&self) -> f32 {
         unimplemented!()
     }
 
+    /// Slowest successful download speed of a measurement
+    fn min_dl(&self) -> f32 {
+        unimplemented!()
+    }
+
+    /// Fastest download speed of a measurement
+    fn max_dl(&self) -> f32 {
+        unimplemented!()
+    }
+
+    /// Median of the successful download speeds of a measurement
+    fn median_dl(&self) -> f32 {
+        unimplemented!()
+    }
+
+    /// Total number of bytes transferred by the download tests of a measurement
+    fn total_bytes_downloaded(&self) -> u64 {
+        unimplemented!()
+    }
+
+    /// Mean upload speed for a measurement
+    fn mean_ul(&self) -> f32 {
+        unimplemented!()
+    }
+
     /// Mean latency for a measurement
     fn mean_latency(&self) -> Duration {
         unimplemented!()
     }
 
+    /// Jitter of a measurement: the mean absolute difference between consecutive
+    /// latency samples of the same target. Timeouts are skipped.
+    fn jitter(&self) -> Duration {
+        unimplemented!()
+    }
+
+    /// Standard deviation of the latency for a measurement
+    fn latency_stddev(&self) -> Duration {
+        unimplemented!()
+    }
+
+    /// Latency percentile of a measurement, using linear interpolation between samples.
+    /// `pct` is clamped to 0-1. Returns `None` if there are no latency samples.
+    #[allow(unused_variables)]
+    fn latency_percentile(&self, pct: f64) -> Option<Duration> {
+        unimplemented!()
+    }
+
+    /// Median latency of a measurement
+    fn latency_p50(&self) -> Option<Duration> {
+        self.latency_percentile(0.5)
+    }
+
+    /// 95th percentile latency of a measurement
+    fn latency_p95(&self) -> Option<Duration> {
+        self.latency_percentile(0.95)
+    }
+
+    /// 99th percentile latency of a measurement
+    fn latency_p99(&self) -> Option<Duration> {
+        self.latency_percentile(0.99)
+    }
+
+    /// Count the latencies that did not time out in buckets of `bucket_ms` width, starting at 0,
+    /// as `(lower bound in ms, count)`. The last bucket is open-ended: latencies beyond
+    /// [HISTOGRAM_BUCKETS] buckets are counted in it. Empty if there are no samples.
+    #[allow(unused_variables)]
+    fn latency_histogram(&self, bucket_ms: f64) -> Vec<(f64, usize)> {
+        unimplemented!()
+    }
+
+    /// Mean time of all successful DNS lookups in a measurement
+    fn mean_dns_lookup(&self) -> Duration {
+        unimplemented!()
+    }
+
+    /// Simple moving average of the latency over the last `window` samples of the same target,
+    /// skipping timeouts. At the start of the measurement fewer samples are averaged.
+    /// With several targets, their averages are interleaved, see [Evaluation::for_target].
+    #[allow(unused_variables)]
+    fn latency_moving_average(&self, window: usize) -> Vec<(SystemTime, Duration)> {
+        unimplemented!()
+    }
+
+    /// Slope of a linear regression of the latency over time in ms per minute, skipping timeouts.
+    /// A positive trend means the line is getting worse. 0 with fewer than two samples.
+    fn latency_trend(&self) -> f64 {
+        unimplemented!()
+    }
+
+    /// Slope of a linear regression of the successful download speeds over time in Mbit/s
+    /// per minute. A negative trend means the line is getting worse. 0 with fewer than two samples.
+    fn download_trend(&self) -> f64 {
+        unimplemented!()
+    }
+
     /// Sum of all timeouts in a measurement
     fn timeouts(&self) -> usize {
         unimplemented!()
     }
 
-    /// Fraction of timeouts fot the measurements, 0-1, where
+    /// All ping targets present in a measurement
+    fn ping_targets(&self) -> Vec<String> {
+        unimplemented!()
+    }
+
+    /// A copy of the measurement that only contains the latency samples of one
+    /// ping target, so all latency methods can be evaluated per target
+    #[allow(unused_variables)]
+    fn for_target(&self, target: &str) -> MeasurementResult {
+        unimplemented!()
+    }
+
+    /// Split the latency samples into consecutive buckets of length `bucket`, starting at the
+    /// first ping, and return `(start, timeouts, total)` for each. Buckets without any
+    /// samples are included with a total of 0, so gaps can be told apart from periods without loss.
+    /// Buckets too short for the measurement are widened to return at most [MAX_TIMEOUT_BUCKETS].
+    #[allow(unused_variables)]
+    fn timeouts_by_bucket(&self, bucket: Duration) -> Vec<(SystemTime, usize, usize)> {
+        unimplemented!()
+    }
+
+    /// Runs of at least `min_consecutive` timeouts in a row of a single target, as
+    /// `(start, end, timeouts)`, sorted by their start. An outage ends with the next successful
+    /// ping of its target. If the measurement ends during an outage, it ends at the last timeout.
+    #[allow(unused_variables)]
+    fn outages(&self, min_consecutive: usize) -> Vec<(SystemTime, SystemTime, usize)> {
+        unimplemented!()
+    }
+
+    /// A copy of the measurement that only contains datapoints recorded from
+    /// `start` (inclusive) up to `end` (exclusive). A reversed range is empty.
+    #[allow(unused_variables)]
+    fn between(&self, start: SystemTime, end: SystemTime) -> MeasurementResult {
+        unimplemented!()
+    }
+
+    /// The datapoints grouped by the network they were measured on, see [Datapoint::Network].
+    /// Datapoints before the first detected network are in the [UNKNOWN_NETWORK] group.
+    fn by_network(&self) -> BTreeMap<String, MeasurementResult> {
+        unimplemented!()
+    }
+
+    /// Number of ping replies that arrived after a reply to a later ping of the same target,
+    /// judged by their sequence numbers. Pings without a sequence number are ignored.
+    fn reordered_count(&self) -> usize {
+        unimplemented!()
+    }
+
+    /// A copy of the measurement reduced to about `max_points` datapoints, e.g. for plotting
+    /// long sessions. Each series (kind and target or url) gets an equal share. A series is
+    /// split into buckets, of which only the lowest and highest value are kept so spikes stay
+    /// visible. Failed measurements count as the highest value.
+    #[allow(unused_variables)]
+    fn downsample(&self, max_points: usize) -> MeasurementResult {
+        unimplemented!()
+    }
+
+    /// Fraction of pings that timed out, 0-1, where
     /// 0 is perfect availability and 1 is complete data loss.
     fn timeouts_for_session(&self) -> f32 {
         unimplemented!()
     }
 
-    /// Save the measurement to a file
+    /// Fraction of pings that were answered, 0-1. Pings that timed out or failed with an
+    /// error, e.g. as the host was unreachable, were not. A measurement without pings is fully available.
+    fn availability(&self) -> f32 {
+        unimplemented!()
+    }
+
+    /// [Evaluation::availability] as a number of nines, e.g. 3.0 for 99.9 %.
+    /// Infinite if no ping timed out.
+    fn availability_nines(&self) -> f64 {
+        unimplemented!()
+    }
+
+    /// Connection quality as a mean opinion score from 1.0 (bad) to 5.0 (excellent),
+    /// following a simplified ITU-T G.107 E-model: the mean latency plus twice the jitter
+    /// (plus 10 ms for codecs) lowers the rating factor R by 1 per 40 ms up to 160 ms and
+    /// by 1 per 10 ms beyond, each percent of packet loss lowers it by 2.5.
+    /// A measurement without pings scores 1.0.
+    fn quality_score(&self) -> f32 {
+        unimplemented!()
+    }
+
+    /// How much higher the median latency is while a download test runs than while the line
+    /// is idle, see [crate::MeasurementBuilder::bufferbloat]. The time a download took is
+    /// derived from its speed and size. `None` without pings both during and between downloads.
+    fn bufferbloat_ms(&self) -> Option<Duration> {
+        unimplemented!()
+    }
+
+    /// How `other` differs from this measurement in aggregate statistics, e.g. after
+    /// changing routers. Sessions don't need to overlap or have the same length.
+    #[allow(unused_variables)]
+    fn compare(&self, other: &MeasurementResult) -> Comparison {
+        unimplemented!()
+    }
+
+    /// The main statistics of the measurement in one serializable struct
+    fn summary(&self) -> Summary {
+        unimplemented!()
+    }
+
+    /// The [Evaluation::summary] as JSON
+    fn summary_json(&self) -> String {
+        unimplemented!()
+    }
+
+    /// Render the latest latency per target, the latest download speed and the
+    /// number of timeouts in the Prometheus exposition format
+    fn to_prometheus(&self) -> String {
+        unimplemented!()
+    }
+
+    /// Save the measurement to a file in the current [LOG_FORMAT_VERSION].
+    /// It is gzip compressed if the file name ends in `.ltst.gz`.
     #[allow(unused_variables)]
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
         unimplemented!()
     }
 
-    /// Load a file into a measurement
+    /// Save the measurement like [Evaluation::save], along with where it was recorded
+    #[allow(unused_variables)]
+    fn save_with_metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        unimplemented!()
+    }
+
+    /// Load a file into a measurement. Compressed logs, JSONL logs and logs of older
+    /// format versions are detected automatically.
     #[allow(unused_variables)]
     fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
         unimplemented!()
     }
 
-    /// Total duration of a measurement, from first sample to last
+    /// The latencies in microseconds in a histogram of fixed size, from which any percentile
+    /// can be read in constant time, e.g. `latency_hdr().value_at_quantile(0.99)`.
+    /// Timeouts are not recorded, latencies beyond [HDR_MAX_LATENCY] are recorded as it.
+    #[cfg(feature = "hdr")]
+    fn latency_hdr(&self) -> hdrhistogram::Histogram<u64> {
+        unimplemented!()
+    }
+
+    /// Save the measurement to an SQLite database, replacing its previous contents
+    #[cfg(feature = "sqlite")]
+    #[allow(unused_variables)]
+    fn save_sqlite<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        unimplemented!()
+    }
+
+    /// Load a measurement from an SQLite database
+    #[cfg(feature = "sqlite")]
+    #[allow(unused_variables)]
+    fn load_sqlite<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        unimplemented!()
+    }
+
+    /// Save the measurement as CSV with one row per datapoint. Timestamps are UTC unix milliseconds,
+    /// latencies are in ms and throughput in Mbit/s.
+    #[allow(unused_variables)]
+    fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        unimplemented!()
+    }
+
+    /// Total duration of a measurement, from the earliest sample to the latest
     fn duration(&self) -> Duration {
         unimplemented!()
     }
 }
 
+/// The change from one measurement to another, see [Evaluation::compare].
+/// Positive values mean `other` is higher.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Comparison {
+    /// Change in mean latency in ms
+    pub mean_latency_ms: f64,
+    /// Change in jitter in ms
+    pub jitter_ms: f64,
+    /// Change in mean download speed in Mbit/s
+    pub download_mbit: f32,
+    /// Change in the fraction of pings that timed out, -1 to 1
+    pub timeout_rate: f32,
+}
+
+/// Number of timeouts in a row that count as an outage in a [Summary]
+pub const OUTAGE_MIN_TIMEOUTS: usize = 3;
+
+/// The main statistics of a measurement, see [Evaluation::summary].
+/// Latencies are in ms, speeds in Mbit/s and the duration in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub mean_latency_ms: f64,
+    /// `None` without successful pings
+    pub median_latency_ms: Option<f64>,
+    /// `None` without successful pings
+    pub p95_latency_ms: Option<f64>,
+    pub jitter_ms: f64,
+    pub mean_download_mbit: f32,
+    pub min_download_mbit: f32,
+    pub max_download_mbit: f32,
+    pub timeouts: usize,
+    /// Fraction of pings that timed out, 0-1
+    pub timeout_fraction: f32,
+    /// Fraction of pings that were answered, 0-1
+    pub availability: f32,
+    /// Runs of at least [OUTAGE_MIN_TIMEOUTS] timeouts
+    pub outages: usize,
+    pub duration_secs: f64,
+    /// See [Evaluation::latency_trend]
+    #[serde(default)]
+    pub latency_trend_ms_per_min: f64,
+    /// See [Evaluation::download_trend]
+    #[serde(default)]
+    pub download_trend_mbit_per_min: f64,
+}
+
+/// The highest latency [Evaluation::latency_hdr] tells apart
+#[cfg(feature = "hdr")]
+pub const HDR_MAX_LATENCY: Duration = Duration::from_secs(60);
+
+/// The significant decimal digits [Evaluation::latency_hdr] keeps of each latency
+#[cfg(feature = "hdr")]
+const HDR_SIGNIFICANT_DIGITS: u8 = 3;
+
+/// The version of the log format written by [Evaluation::save]. Version 1 logs
+/// are a bare array of datapoints, later versions wrap them with their version.
+/// Version 3 records the HTTP version of per-url downloads.
+pub const LOG_FORMAT_VERSION: u32 = 3;
+
+/// A saved log along with the version of its format
+#[derive(Serialize, Deserialize)]
+struct LogFile<D> {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+    datapoints: D,
+}
+
+/// A log in the binary format. Unlike a [LogFile], all fields are always written,
+/// as the format has no field names to tell which are missing.
+#[derive(Serialize, Deserialize)]
+struct BinaryLogFile<D> {
+    version: u32,
+    metadata: Option<Metadata>,
+    datapoints: D,
+}
+
+/// A datapoint of a version 2 binary log, before per-url downloads recorded their HTTP
+/// version. The binary format has no optional fields, so each version keeps its layout.
+#[derive(Deserialize)]
+enum DatapointV2 {
+    Latency(String, Option<Duration>, SystemTime, Option<u64>),
+    ThroughputUp(Option<f32>, SystemTime),
+    ThroughputDown(Option<f32>, SystemTime),
+    ThroughputDownPerUrl(String, Option<f32>, SystemTime),
+    DnsLookup(String, Option<Duration>, SystemTime),
+    BytesDownloaded(u64, SystemTime),
+    LatencyError(String, String, SystemTime),
+    Network(Option<String>, SystemTime),
+}
+
+impl From<DatapointV2> for Datapoint {
+    fn from(dp: DatapointV2) -> Self {
+        match dp {
+            DatapointV2::Latency(target, l, t, seq) => Datapoint::Latency(target, l, t, seq),
+            DatapointV2::ThroughputUp(up, t) => Datapoint::ThroughputUp(up, t),
+            DatapointV2::ThroughputDown(dn, t) => Datapoint::ThroughputDown(dn, t),
+            DatapointV2::ThroughputDownPerUrl(url, dn, t) => {
+                Datapoint::ThroughputDownPerUrl(url, dn, t, None)
+            }
+            DatapointV2::DnsLookup(host, l, t) => Datapoint::DnsLookup(host, l, t),
+            DatapointV2::BytesDownloaded(bytes, t) => Datapoint::BytesDownloaded(bytes, t),
+            DatapointV2::LatencyError(target, e, t) => Datapoint::LatencyError(target, e, t),
+            DatapointV2::Network(network, t) => Datapoint::Network(network, t),
+        }
+    }
+}
+
+/// Read a log in the binary format, migrating the datapoints of older versions.
+/// Binary logs were introduced with version 2.
+fn read_binary_log(path: &Path) -> Result<BinaryLogFile<MeasurementResult>, Error> {
+    let bytes = std::fs::read(path)?;
+    // the version comes first, check it before reading datapoints of a different layout
+    let version: u32 = bincode::deserialize(&bytes)?;
+    match version {
+        2 => {
+            let log: BinaryLogFile<Vec<DatapointV2>> = bincode::deserialize(&bytes)?;
+            Ok(BinaryLogFile {
+                version: log.version,
+                metadata: log.metadata,
+                datapoints: log.datapoints.into_iter().map(Datapoint::from).collect(),
+            })
+        }
+        LOG_FORMAT_VERSION => Ok(bincode::deserialize(&bytes)?),
+        version => Err(anyhow!(
+            "Binary log format version {} can't be read, only versions 2 to {} are supported",
+            version,
+            LOG_FORMAT_VERSION
+        )),
+    }
+}
+
+/// The first line of a JSONL log, before the datapoints
+#[derive(Serialize, Deserialize)]
+struct MetadataHeader {
+    metadata: Metadata,
+}
+
+/// Read a log file, decompressing it if needed
+fn read_log(path: &Path) -> Result<String, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    // gzip streams start with these magic bytes
+    let mut reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// The first JSON value of a log if it is an object: the whole log if it is versioned,
+/// the metadata or first datapoint of a JSONL log
+fn first_object(content: &str) -> Option<serde_json::Value> {
+    if !content.trim_start().starts_with('{') {
+        return None;
+    }
+    serde_json::Deserializer::from_str(content)
+        .into_iter::<serde_json::Value>()
+        .next()?
+        .ok()
+}
+
+/// Where the log at `path` was recorded, `None` if it doesn't say
+pub fn load_metadata<P: AsRef<Path>>(path: P) -> Result<Option<Metadata>, Error> {
+    if is_binary_log(path.as_ref()) {
+        return Ok(read_binary_log(path.as_ref())?.metadata);
+    }
+    let first = match first_object(&read_log(path.as_ref())?) {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    match first.get("metadata") {
+        Some(metadata) => Ok(Some(serde_json::from_value(metadata.clone())?)),
+        None => Ok(None),
+    }
+}
+
+/// The target of latencies recorded before logs named the ping target
+const UNKNOWN_TARGET: &str = "unknown target";
+
+/// A datapoint of a version 1 log, a bare array of datapoints. The oldest of them don't
+/// name the ping target of a latency yet, and per-url downloads don't have an HTTP version.
+#[derive(Deserialize)]
+enum DatapointV1 {
+    Latency(LatencyV1),
+    ThroughputUp(Option<f32>, SystemTime),
+    ThroughputDown(Option<f32>, SystemTime),
+    ThroughputDownPerUrl(String, Option<f32>, SystemTime),
+    DnsLookup(String, Option<Duration>, SystemTime),
+    BytesDownloaded(u64, SystemTime),
+    LatencyError(String, String, SystemTime),
+}
+
+/// A latency of a version 1 log, with or without its target
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LatencyV1 {
+    Targeted(
+        String,
+        Option<Duration>,
+        SystemTime,
+        #[serde(default)] Option<u64>,
+    ),
+    Untargeted(Option<Duration>, SystemTime),
+}
+
+impl From<DatapointV1> for Datapoint {
+    fn from(dp: DatapointV1) -> Self {
+        match dp {
+            DatapointV1::Latency(LatencyV1::Targeted(target, l, t, seq)) => {
+                Datapoint::Latency(target, l, t, seq)
+            }
+            DatapointV1::Latency(LatencyV1::Untargeted(l, t)) => {
+                Datapoint::Latency(UNKNOWN_TARGET.to_string(), l, t, None)
+            }
+            DatapointV1::ThroughputUp(up, t) => Datapoint::ThroughputUp(up, t),
+            DatapointV1::ThroughputDown(dn, t) => Datapoint::ThroughputDown(dn, t),
+            DatapointV1::ThroughputDownPerUrl(url, dn, t) => {
+                Datapoint::ThroughputDownPerUrl(url, dn, t, None)
+            }
+            DatapointV1::DnsLookup(host, l, t) => Datapoint::DnsLookup(host, l, t),
+            DatapointV1::BytesDownloaded(bytes, t) => Datapoint::BytesDownloaded(bytes, t),
+            DatapointV1::LatencyError(target, e, t) => Datapoint::LatencyError(target, e, t),
+        }
+    }
+}
+
+/// Read the datapoints of a version 1 log
+fn load_v1(datapoints: serde_json::Value) -> Result<MeasurementResult, Error> {
+    let datapoints: Vec<DatapointV1> = serde_json::from_value(datapoints)?;
+    Ok(datapoints.into_iter().map(Datapoint::from).collect())
+}
+
+/// Read the datapoints of a versioned log, migrating them from older formats
+fn migrate(log: LogFile<serde_json::Value>) -> Result<MeasurementResult, Error> {
+    match log.version {
+        1 => load_v1(log.datapoints),
+        // the datapoints only gained optional fields since version 2
+        2..=LOG_FORMAT_VERSION => Ok(serde_json::from_value(log.datapoints)?),
+        version => Err(anyhow!(
+            "Log format version {} is newer than the supported version {}",
+            version,
+            LOG_FORMAT_VERSION
+        )),
+    }
+}
+
+/// Save `result` as a versioned log, see [Evaluation::save]
+fn save_log(
+    result: &MeasurementResult,
+    path: &Path,
+    metadata: Option<&Metadata>,
+) -> Result<(), Error> {
+    // make sure parent dir exists
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            create_dir_all(parent).with_context(|| format!("Can't create {}", parent.display()))?;
+        }
+    }
+    // write to a temporary file first, so a crash while writing never truncates the log
+    let tmp = path.with_extension("tmp");
+    let mut f = BufWriter::new(
+        File::create(&tmp).with_context(|| format!("Can't write {}", path.display()))?,
+    );
+    if is_binary_log(path) {
+        let log = BinaryLogFile {
+            version: LOG_FORMAT_VERSION,
+            metadata: metadata.cloned(),
+            datapoints: result,
+        };
+        bincode::serialize_into(&mut f, &log)?;
+    } else {
+        let log = LogFile {
+            version: LOG_FORMAT_VERSION,
+            datapoints: result,
+            metadata: metadata.cloned(),
+        };
+        if is_compressed_log(path) {
+            let mut encoder = GzEncoder::new(f, Compression::default());
+            serde_json::to_writer(&mut encoder, &log)?;
+            f = encoder.finish()?;
+        } else {
+            serde_json::to_writer(&mut f, &log)?;
+        }
+    }
+    f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Append a single datapoint to a JSONL log, one JSON object per line. This is cheap
+/// to call for every new datapoint, and a crash only loses the line being written.
+/// Logs written this way are read with [Evaluation::load].
+pub fn append_datapoint<P: AsRef<Path>>(path: P, dp: &Datapoint) -> Result<(), Error> {
+    append_line(path.as_ref(), dp)
+}
+
+/// Start a JSONL log with where it is recorded
+pub(crate) fn append_metadata(path: &Path, metadata: &Metadata) -> Result<(), Error> {
+    append_line(
+        path,
+        &MetadataHeader {
+            metadata: metadata.clone(),
+        },
+    )
+}
+
+/// Append `value` to a JSONL log as a single line
+fn append_line<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if is_compressed_log(path) || is_binary_log(path) {
+        return Err(anyhow!(
+            "Can't append to compressed or binary log {}",
+            path.display()
+        ));
+    }
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            create_dir_all(parent).with_context(|| format!("Can't create {}", parent.display()))?;
+        }
+    }
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    // write the line at once so it is not interleaved with other writes
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Can't write {}", path.display()))?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// Create `dir` if needed and make sure files can be written to it, by writing an empty probe
+pub(crate) fn ensure_writable(dir: &Path) -> Result<(), Error> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    create_dir_all(dir)
+        .with_context(|| format!("Can't create the data directory {}", dir.display()))?;
+    let probe = dir.join(format!(".linetest-{}.probe", std::process::id()));
+    File::create(&probe)
+        .with_context(|| format!("The data directory {} is not writable", dir.display()))?;
+    let _ = remove_file(&probe);
+    Ok(())
+}
+
+/// Drop the oldest datapoints of a JSONL log, so what is left fits into half a byte budget
+/// or the number of datapoints of `capacity`. The metadata at the start is kept.
+pub(crate) fn trim_jsonl(path: &Path, capacity: LogCapacity) -> Result<(), Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut lines = content.lines().collect::<Vec<_>>();
+    let header = match lines.first() {
+        Some(first) if serde_json::from_str::<MetadataHeader>(first).is_ok() => {
+            Some(lines.remove(0))
+        }
+        _ => None,
+    };
+    let first_kept = match capacity {
+        LogCapacity::Datapoints(max) => lines.len().saturating_sub(max),
+        LogCapacity::Bytes(max) => {
+            // leave room to append, so the log isn't trimmed again right away
+            let mut budget =
+                (max / 2).saturating_sub(header.map(|h| h.len() as u64 + 1).unwrap_or_default());
+            let mut first_kept = lines.len();
+            while let Some(line) = first_kept.checked_sub(1).map(|i| lines[i]) {
+                match budget.checked_sub(line.len() as u64 + 1) {
+                    Some(left) => budget = left,
+                    None => break,
+                }
+                first_kept -= 1;
+            }
+            first_kept
+        }
+    };
+    if first_kept == 0 {
+        return Ok(());
+    }
+
+    // write to a temporary file first, so a crash while trimming never loses the log
+    let tmp = path.with_extension("tmp");
+    let mut f = BufWriter::new(File::create(&tmp)?);
+    for line in header.iter().chain(&lines[first_kept..]) {
+        f.write_all(line.as_bytes())?;
+        f.write_all(b"\n")?;
+    }
+    f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Read a JSONL log line by line. An incomplete last line, e.g. from a crash while
+/// recording, is skipped.
+fn load_jsonl<R: BufRead>(reader: R) -> Result<MeasurementResult, Error> {
+    let mut result = vec![];
+    let mut lines = reader.lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // the metadata line at the start
+        if result.is_empty() && serde_json::from_str::<MetadataHeader>(&line).is_ok() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(dp) => result.push(dp),
+            Err(_) if lines.peek().is_none() => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(result)
+}
+
+/// Load multiple measurement files into one measurement, sorted by time
+pub fn merge(files: &[PathBuf]) -> Result<MeasurementResult, Error> {
+    let mut merged: MeasurementResult = vec![];
+    for file in files {
+        let mut result: MeasurementResult = vec![];
+        result.load(file)?;
+        merged.extend(result);
+    }
+    merged.sort_by_key(Datapoint::timestamp);
+    Ok(merged)
+}
+
+/// The kind, value, timestamp and source (ping target, url or hostname) of a datapoint,
+/// as written to tabular formats. Durations are in ms, speeds in Mbit/s. The kinds of
+/// measurements of the warm-up start with `warmup_`, so they can be left out like [Evaluation] does.
+pub(crate) fn flat_fields(dp: &Datapoint) -> (&'static str, Option<f64>, &SystemTime, &str) {
+    match dp {
+        Datapoint::Latency(target, l, t, _) => (
+            "latency",
+            l.map(|l| l.as_secs_f64() * 1000.),
+            t,
+            target.as_str(),
+        ),
+        Datapoint::ThroughputDown(tp, t) => ("throughput_down", tp.map(f64::from), t, ""),
+        Datapoint::ThroughputUp(tp, t) => ("throughput_up", tp.map(f64::from), t, ""),
+        Datapoint::ThroughputDownPerUrl(url, tp, t, _) => (
+            "throughput_down_per_url",
+            tp.map(f64::from),
+            t,
+            url.as_str(),
+        ),
+        Datapoint::DnsLookup(hostname, l, t) => (
+            "dns_lookup",
+            l.map(|l| l.as_secs_f64() * 1000.),
+            t,
+            hostname.as_str(),
+        ),
+        Datapoint::BytesDownloaded(bytes, t) => ("bytes_downloaded", Some(*bytes as f64), t, ""),
+        Datapoint::LatencyError(target, _, t) => ("latency_error", None, t, target.as_str()),
+        Datapoint::Network(network, t) => (
+            "network",
+            None,
+            t,
+            network.as_deref().unwrap_or(UNKNOWN_NETWORK),
+        ),
+        Datapoint::Warmup(dp) => {
+            let (kind, value, t, source) = flat_fields(dp);
+            (warmup_kind(kind), value, t, source)
+        }
+    }
+}
+
+/// The kind of a measurement of the warm-up in tabular formats, see [flat_fields]
+fn warmup_kind(kind: &'static str) -> &'static str {
+    match kind {
+        "latency" => "warmup_latency",
+        "throughput_down" => "warmup_throughput_down",
+        "throughput_up" => "warmup_throughput_up",
+        "throughput_down_per_url" => "warmup_throughput_down_per_url",
+        "dns_lookup" => "warmup_dns_lookup",
+        "latency_error" => "warmup_latency_error",
+        // not measured, see [Datapoint::warmup]
+        kind => kind,
+    }
+}
+
+/// Quote a CSV field if it contains characters that would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Escape a Prometheus label value
+fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// All combined download speeds of a measurement that did not fail, sorted ascending
+fn valid_download_speeds(result: &MeasurementResult) -> Vec<f32> {
+    let mut speeds = result
+        .downloads()
+        .filter_map(|(_, dn)| dn)
+        .collect::<Vec<_>>();
+    speeds.sort_by(|a, b| a.total_cmp(b));
+    speeds
+}
+
+/// Half the range of ICMP sequence numbers. A sequence number that is lower than
+/// the highest one seen by more than this has wrapped around.
+const SEQUENCE_WRAP: u64 = 1 << 15;
+
+/// The maximum number of buckets of a latency histogram
+pub const HISTOGRAM_BUCKETS: usize = 50;
+
+/// The maximum number of buckets of [Evaluation::timeouts_by_bucket]
+pub const MAX_TIMEOUT_BUCKETS: usize = 10_000;
+
+/// All latencies of a measurement that did not time out, in recorded order
+fn valid_latencies(result: &MeasurementResult) -> Vec<Duration> {
+    result.latencies().filter_map(|(_, l)| l).collect()
+}
+
+/// Slope of the least squares line through `samples` per minute. 0 with fewer than
+/// two samples or if they were all taken at the same time.
+fn slope_per_minute(samples: &[(SystemTime, f64)]) -> f64 {
+    let first = match samples.first() {
+        Some((t, _)) if samples.len() >= 2 => *t,
+        _ => return 0.0,
+    };
+    let points = samples
+        .iter()
+        .map(|(t, y)| {
+            (
+                t.duration_since(first).unwrap_or_default().as_secs_f64() / 60.,
+                *y,
+            )
+        })
+        .collect::<Vec<_>>();
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let variance = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+    if variance == 0.0 {
+        return 0.0;
+    }
+    covariance / variance
+}
+
 impl Evaluation for MeasurementResult {
+    fn latencies(&self) -> impl Iterator<Item = (SystemTime, Option<Duration>)> + '_ {
+        self.iter().filter_map(|e| match e {
+            Datapoint::Latency(_, l, t, _) => Some((*t, *l)),
+            _ => None,
+        })
+    }
+
+    fn downloads(&self) -> impl Iterator<Item = (SystemTime, Option<f32>)> + '_ {
+        self.iter().filter_map(|e| match e {
+            Datapoint::ThroughputDown(dn, t) => Some((*t, *dn)),
+            _ => None,
+        })
+    }
+
     fn mean_dl(&self) -> f32 {
+        let count = self.downloads().count();
+
+        // prevent div/0 error
+        if count == 0 {
+            return 0.0;
+        }
+
+        self.downloads()
+            .map(|(_, dn)| dn.unwrap_or_default())
+            .sum::<f32>()
+            / count as f32
+    }
+
+    fn min_dl(&self) -> f32 {
+        valid_download_speeds(self)
+            .first()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn max_dl(&self) -> f32 {
+        valid_download_speeds(self)
+            .last()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn median_dl(&self) -> f32 {
+        let speeds = valid_download_speeds(self);
+        match speeds.len() {
+            0 => 0.0,
+            n if n % 2 == 0 => (speeds[n / 2 - 1] + speeds[n / 2]) / 2.,
+            n => speeds[n / 2],
+        }
+    }
+
+    fn total_bytes_downloaded(&self) -> u64 {
+        self.iter()
+            .map(|e| match e {
+                Datapoint::BytesDownloaded(bytes, _) => *bytes,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn mean_ul(&self) -> f32 {
         let count = self
             .iter()
-            .filter(|e| match e {
-                Datapoint::ThroughputDown(_, _) => true,
-                _ => false,
-            })
+            .filter(|e| matches!(e, Datapoint::ThroughputUp(_, _)))
             .count();
 
+        // prevent div/0 error
+        if count == 0 {
+            return 0.0;
+        }
+
         self.iter().fold(0.0, |acc, e| match e {
-            Datapoint::ThroughputDown(dn, _t) => acc + dn.unwrap_or_default(),
+            Datapoint::ThroughputUp(up, _t) => acc + up.unwrap_or_default(),
             _ => acc,
         }) / count as f32
     }
 
     fn mean_latency(&self) -> Duration {
-        let count = self
+        let latencies = valid_latencies(self);
+
+        // prevent div/0 error
+        if latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+
+        latencies.iter().sum::<Duration>() / latencies.len() as u32
+    }
+
+    fn jitter(&self) -> Duration {
+        // the previous latency of each target, as targets can have very different latencies
+        let mut previous: BTreeMap<&str, Duration> = BTreeMap::new();
+        let mut sum = 0.0;
+        let mut count = 0;
+        for dp in self {
+            if let Datapoint::Latency(target, Some(latency), ..) = dp {
+                if let Some(last) = previous.insert(target.as_str(), *latency) {
+                    sum += (latency.as_secs_f64() - last.as_secs_f64()).abs();
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(sum / count as f64)
+    }
+
+    fn latency_stddev(&self) -> Duration {
+        let latencies = valid_latencies(self);
+        if latencies.len() < 2 {
+            return Duration::ZERO;
+        }
+        let count = latencies.len() as f64;
+        let mean = latencies.iter().map(|l| l.as_secs_f64()).sum::<f64>() / count;
+        let variance = latencies
             .iter()
-            .filter(|e| match e {
-                Datapoint::Latency(d, _) => d.is_some(),
-                _ => false,
+            .map(|l| (l.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Duration::from_secs_f64(variance.sqrt())
+    }
+
+    fn latency_percentile(&self, pct: f64) -> Option<Duration> {
+        let mut latencies = valid_latencies(self);
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let rank = pct.clamp(0.0, 1.0) * (latencies.len() - 1) as f64;
+        let lower = latencies[rank.floor() as usize].as_secs_f64();
+        let upper = latencies[rank.ceil() as usize].as_secs_f64();
+        Some(Duration::from_secs_f64(
+            lower + (upper - lower) * rank.fract(),
+        ))
+    }
+
+    fn latency_histogram(&self, bucket_ms: f64) -> Vec<(f64, usize)> {
+        let latencies = valid_latencies(self);
+        if latencies.is_empty() || bucket_ms.is_nan() || bucket_ms <= 0.0 {
+            return vec![];
+        }
+        let bucket_index = |l: &Duration| {
+            ((l.as_secs_f64() * 1000. / bucket_ms) as usize).min(HISTOGRAM_BUCKETS - 1)
+        };
+        let buckets = latencies.iter().map(bucket_index).max().unwrap_or_default() + 1;
+        let mut counts = vec![0; buckets];
+        for latency in &latencies {
+            counts[bucket_index(latency)] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (i as f64 * bucket_ms, count))
+            .collect()
+    }
+
+    fn mean_dns_lookup(&self) -> Duration {
+        let lookups = self
+            .iter()
+            .filter_map(|e| match e {
+                Datapoint::DnsLookup(_, l, _) => *l,
+                _ => None,
             })
-            .count();
-        
+            .collect::<Vec<_>>();
+
         // prevent div/0 error
-        if count == 0 {
-            return Duration::from_secs(0);
+        if lookups.is_empty() {
+            return Duration::ZERO;
+        }
+        lookups.iter().sum::<Duration>() / lookups.len() as u32
+    }
+
+    fn latency_moving_average(&self, window: usize) -> Vec<(SystemTime, Duration)> {
+        let window = window.max(1);
+        // the most recent samples of each target
+        let mut recent: BTreeMap<&str, VecDeque<Duration>> = BTreeMap::new();
+        let mut averages = vec![];
+        for dp in self {
+            if let Datapoint::Latency(target, Some(latency), t, _) = dp {
+                let values = recent.entry(target.as_str()).or_default();
+                values.push_back(*latency);
+                if values.len() > window {
+                    values.pop_front();
+                }
+                averages.push((*t, values.iter().sum::<Duration>() / values.len() as u32));
+            }
         }
+        averages
+    }
 
+    fn latency_trend(&self) -> f64 {
+        let samples = self
+            .latencies()
+            .filter_map(|(t, l)| l.map(|l| (t, l.as_secs_f64() * 1000.)))
+            .collect::<Vec<_>>();
+        slope_per_minute(&samples)
+    }
+
+    fn download_trend(&self) -> f64 {
+        let samples = self
+            .downloads()
+            .filter_map(|(t, dn)| dn.map(|dn| (t, dn as f64)))
+            .collect::<Vec<_>>();
+        slope_per_minute(&samples)
+    }
+
+    fn timeouts(&self) -> usize {
+        self.iter()
+            .filter(|e| e.is_latency() && e.is_timeout())
+            .count()
+    }
+
+    fn ping_targets(&self) -> Vec<String> {
+        let mut targets = vec![];
+        for dp in self {
+            if let Datapoint::Latency(target, ..) = dp {
+                if !targets.contains(target) {
+                    targets.push(target.clone());
+                }
+            }
+        }
+        targets
+    }
+
+    fn for_target(&self, target: &str) -> MeasurementResult {
         self.iter()
             .filter(|e| match e {
-                Datapoint::Latency(d, _) => d.is_some(),
+                Datapoint::Latency(t, ..) => t == target,
                 _ => false,
             })
-            .fold(Duration::from_secs(0), |acc, e| match e {
-                //TODO: using anything as mean calculation is not good, maybe skip these values?
-                Datapoint::Latency(l, _t) => acc + l.unwrap_or(Duration::from_secs(0)),
-                _ => acc,
-            })
-            / count as u32
+            .cloned()
+            .collect()
     }
 
-    fn timeouts(&self) -> usize {
+    fn timeouts_by_bucket(&self, bucket: Duration) -> Vec<(SystemTime, usize, usize)> {
+        let pings = self
+            .latencies()
+            .map(|(t, l)| (t, l.is_none()))
+            .collect::<Vec<_>>();
+        let times = pings.iter().map(|(t, _)| *t);
+        let (start, end) = match (times.clone().min(), times.max()) {
+            (Some(start), Some(end)) if !bucket.is_zero() => (start, end),
+            _ => return vec![],
+        };
+        let span = end.duration_since(start).unwrap_or_default().as_nanos();
+        let bucket = bucket
+            .as_nanos()
+            .max(span / MAX_TIMEOUT_BUCKETS as u128 + 1);
+
+        let mut buckets: Vec<(SystemTime, usize, usize)> = vec![];
+        for (t, timed_out) in pings {
+            let index = (t.duration_since(start).unwrap_or_default().as_nanos() / bucket) as usize;
+            while buckets.len() <= index {
+                let bucket_start = bucket
+                    .checked_mul(buckets.len() as u128)
+                    .and_then(|offset| u64::try_from(offset).ok())
+                    .and_then(|offset| start.checked_add(Duration::from_nanos(offset)))
+                    .unwrap_or(t);
+                buckets.push((bucket_start, 0, 0));
+            }
+            if timed_out {
+                buckets[index].1 += 1;
+            }
+            buckets[index].2 += 1;
+        }
+        buckets
+    }
+
+    fn outages(&self, min_consecutive: usize) -> Vec<(SystemTime, SystemTime, usize)> {
+        let min_consecutive = min_consecutive.max(1);
+        let mut outages = vec![];
+        // start, last timeout and length of the current run of timeouts of each target
+        let mut runs: BTreeMap<&str, (SystemTime, SystemTime, usize)> = BTreeMap::new();
+        for dp in self {
+            let (target, latency, t) = match dp {
+                Datapoint::Latency(target, latency, t, _) => (target.as_str(), latency, *t),
+                _ => continue,
+            };
+            match latency {
+                None => {
+                    runs.entry(target)
+                        .and_modify(|(_, last, count)| {
+                            *last = t;
+                            *count += 1;
+                        })
+                        .or_insert((t, t, 1));
+                }
+                Some(_) => {
+                    if let Some((start, _, count)) = runs.remove(target) {
+                        if count >= min_consecutive {
+                            outages.push((start, t, count));
+                        }
+                    }
+                }
+            }
+        }
+        // still ongoing
+        outages.extend(
+            runs.into_values()
+                .filter(|(_, _, count)| *count >= min_consecutive),
+        );
+        outages.sort_by_key(|(start, ..)| *start);
+        outages
+    }
+
+    fn between(&self, start: SystemTime, end: SystemTime) -> MeasurementResult {
         self.iter()
-            .filter(|e| match e {
-                Datapoint::Latency(l, _) => l.is_none(),
-                _ => false,
+            .filter(|dp| {
+                let t = dp.timestamp();
+                t >= start && t < end
             })
-            .count()
+            .cloned()
+            .collect()
+    }
+
+    fn by_network(&self) -> BTreeMap<String, MeasurementResult> {
+        let mut groups: BTreeMap<String, MeasurementResult> = BTreeMap::new();
+        let mut network = UNKNOWN_NETWORK;
+        for dp in self {
+            match dp {
+                Datapoint::Network(name, _) => network = name.as_deref().unwrap_or(UNKNOWN_NETWORK),
+                dp => groups
+                    .entry(network.to_string())
+                    .or_default()
+                    .push(dp.clone()),
+            }
+        }
+        groups
+    }
+
+    fn reordered_count(&self) -> usize {
+        // highest sequence number answered so far, per target
+        let mut highest: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut reordered = 0;
+        for dp in self {
+            if let Datapoint::Latency(target, Some(_), _, Some(sequence)) = dp {
+                match highest.get(target.as_str()) {
+                    // a large step back is the 16 bit sequence number of ping wrapping around
+                    Some(max) if sequence < max && max - sequence < SEQUENCE_WRAP => reordered += 1,
+                    _ => {
+                        highest.insert(target, *sequence);
+                    }
+                }
+            }
+        }
+        reordered
+    }
+
+    fn downsample(&self, max_points: usize) -> MeasurementResult {
+        if self.len() <= max_points {
+            return self.clone();
+        }
+        let mut series: BTreeMap<(&str, &str), Vec<usize>> = BTreeMap::new();
+        for (i, dp) in self.iter().enumerate() {
+            let (kind, _, _, source) = flat_fields(dp);
+            series.entry((kind, source)).or_default().push(i);
+        }
+
+        let per_series = (max_points / series.len()).max(2);
+        let value = |i: &usize| flat_fields(&self[*i]).1.unwrap_or(f64::INFINITY);
+        let mut keep: Vec<usize> = vec![];
+        for indices in series.values() {
+            if indices.len() <= per_series {
+                keep.extend(indices);
+                continue;
+            }
+            let buckets = per_series / 2;
+            for bucket in 0..buckets {
+                let chunk = &indices
+                    [bucket * indices.len() / buckets..(bucket + 1) * indices.len() / buckets];
+                let lowest = chunk.iter().min_by(|a, b| value(a).total_cmp(&value(b)));
+                let highest = chunk.iter().max_by(|a, b| value(a).total_cmp(&value(b)));
+                keep.extend(lowest.into_iter().chain(highest));
+            }
+        }
+        keep.sort_unstable();
+        keep.dedup();
+        keep.into_iter().map(|i| self[i].clone()).collect()
     }
 
     fn timeouts_for_session(&self) -> f32 {
-        self.timeouts() as f32 / self.len() as f32
+        let pings = self.iter().filter(|e| e.is_latency()).count();
+        if pings == 0 {
+            return 0.0;
+        }
+        self.timeouts() as f32 / pings as f32
+    }
+
+    fn availability(&self) -> f32 {
+        let (answered, pings) = self.iter().fold((0, 0), |(answered, pings), dp| match dp {
+            Datapoint::Latency(_, Some(_), ..) => (answered + 1, pings + 1),
+            Datapoint::Latency(_, None, ..) | Datapoint::LatencyError(..) => (answered, pings + 1),
+            _ => (answered, pings),
+        });
+        if pings == 0 {
+            return 1.0;
+        }
+        answered as f32 / pings as f32
+    }
+
+    fn availability_nines(&self) -> f64 {
+        -(1.0 - self.availability() as f64).log10()
+    }
+
+    fn quality_score(&self) -> f32 {
+        if self.latencies().next().is_none() {
+            return 1.0;
+        }
+        let effective_latency = self.mean_latency().as_secs_f32() * 1000.
+            + 2. * self.jitter().as_secs_f32() * 1000.
+            + 10.;
+        let r = if effective_latency < 160. {
+            93.2 - effective_latency / 40.
+        } else {
+            93.2 - (effective_latency - 120.) / 10.
+        } - 2.5 * self.timeouts_for_session() * 100.;
+        if r <= 0.0 {
+            return 1.0;
+        }
+        (1. + 0.035 * r + 0.000007 * r * (r - 60.) * (100. - r)).clamp(1.0, 5.0)
+    }
+
+    fn bufferbloat_ms(&self) -> Option<Duration> {
+        let mut windows = vec![];
+        let mut bytes = 0;
+        for dp in self {
+            match dp {
+                // the combined speed follows the size of the same download test
+                Datapoint::BytesDownloaded(b, _) => bytes = *b,
+                Datapoint::ThroughputDown(Some(mbits), end) if *mbits > 0.0 && bytes > 0 => {
+                    let duration =
+                        Duration::from_secs_f64(bytes as f64 * 8. / 1000. / 1000. / *mbits as f64);
+                    windows.push((end.checked_sub(duration).unwrap_or(*end), *end));
+                }
+                _ => (),
+            }
+        }
+        let (loaded, idle): (MeasurementResult, MeasurementResult) = self
+            .iter()
+            .filter(|dp| dp.is_latency())
+            .cloned()
+            .partition(|dp| {
+                let t = dp.timestamp();
+                windows.iter().any(|(start, end)| *start <= t && t <= *end)
+            });
+        Some(loaded.latency_p50()?.saturating_sub(idle.latency_p50()?))
+    }
+
+    fn compare(&self, other: &MeasurementResult) -> Comparison {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.;
+        Comparison {
+            mean_latency_ms: ms(other.mean_latency()) - ms(self.mean_latency()),
+            jitter_ms: ms(other.jitter()) - ms(self.jitter()),
+            download_mbit: other.mean_dl() - self.mean_dl(),
+            timeout_rate: other.timeouts_for_session() - self.timeouts_for_session(),
+        }
+    }
+
+    fn summary(&self) -> Summary {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.;
+        Summary {
+            mean_latency_ms: ms(self.mean_latency()),
+            median_latency_ms: self.latency_p50().map(ms),
+            p95_latency_ms: self.latency_p95().map(ms),
+            jitter_ms: ms(self.jitter()),
+            mean_download_mbit: self.mean_dl(),
+            min_download_mbit: self.min_dl(),
+            max_download_mbit: self.max_dl(),
+            timeouts: self.timeouts(),
+            timeout_fraction: self.timeouts_for_session(),
+            availability: self.availability(),
+            outages: self.outages(OUTAGE_MIN_TIMEOUTS).len(),
+            duration_secs: self.duration().as_secs_f64(),
+            latency_trend_ms_per_min: self.latency_trend(),
+            download_trend_mbit_per_min: self.download_trend(),
+        }
+    }
+
+    fn summary_json(&self) -> String {
+        serde_json::to_string(&self.summary()).unwrap_or_default()
+    }
+
+    fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP linetest_latency_ms Latest successful ping latency in milliseconds\n");
+        out.push_str("# TYPE linetest_latency_ms gauge\n");
+        for target in self.ping_targets() {
+            let latest = self.iter().rev().find_map(|e| match e {
+                Datapoint::Latency(t, l, ..) if *t == target => *l,
+                _ => None,
+            });
+            if let Some(latency) = latest {
+                out.push_str(&format!(
+                    "linetest_latency_ms{{target=\"{}\"}} {}\n",
+                    prometheus_label(&target),
+                    latency.as_secs_f64() * 1000.
+                ));
+            }
+        }
+        let latest_dl = self.downloads().filter_map(|(_, tp)| tp).last();
+        if let Some(tp) = latest_dl {
+            out.push_str("# HELP linetest_download_mbit Latest download speed in Mbit/s\n");
+            out.push_str("# TYPE linetest_download_mbit gauge\n");
+            out.push_str(&format!("linetest_download_mbit {}\n", tp));
+        }
+        out.push_str("# HELP linetest_timeouts_total Number of pings that timed out\n");
+        out.push_str("# TYPE linetest_timeouts_total counter\n");
+        out.push_str(&format!("linetest_timeouts_total {}\n", self.timeouts()));
+        out
     }
 
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-        // make sure parent dir exists
+        save_log(self, path.as_ref(), None)
+    }
+
+    fn save_with_metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        save_log(self, path.as_ref(), Some(metadata))
+    }
+
+    fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        if is_binary_log(path.as_ref()) {
+            *self = read_binary_log(path.as_ref())?.datapoints;
+            return Ok(());
+        }
+        let content = read_log(path.as_ref())?;
+        // a version 1 log is a single array, a JSONL log is one datapoint object per line
+        // and a versioned log a single object with the version
+        *self = match first_object(&content) {
+            Some(value) if value.get("version").is_some() => {
+                migrate(serde_json::from_value(value)?)?
+            }
+            Some(_) => load_jsonl(content.as_bytes())?,
+            None if content.trim_start().starts_with('{') => load_jsonl(content.as_bytes())?,
+            None => load_v1(serde_json::from_str(&content)?)?,
+        };
+        Ok(())
+    }
+
+    #[cfg(feature = "hdr")]
+    fn latency_hdr(&self) -> hdrhistogram::Histogram<u64> {
+        let mut histogram = hdrhistogram::Histogram::new_with_bounds(
+            1,
+            HDR_MAX_LATENCY.as_micros() as u64,
+            HDR_SIGNIFICANT_DIGITS,
+        )
+        .expect("valid histogram bounds");
+        for (_, latency) in self.latencies() {
+            if let Some(latency) = latency {
+                histogram.saturating_record((latency.as_micros() as u64).max(1));
+            }
+        }
+        histogram
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn save_sqlite<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        super::sqlite::save(self, path)
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn load_sqlite<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        *self = super::sqlite::load(path)?;
+        Ok(())
+    }
+
+    fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
         if let Some(parent) = path.as_ref().parent() {
             if !parent.is_dir() {
                 create_dir_all(parent)?;
             }
         }
-        let f = File::create(path.as_ref())?;
-        serde_json::to_writer(f, self)?;
+        let mut f = BufWriter::new(File::create(path.as_ref())?);
+        writeln!(f, "timestamp_unix_ms,kind,value,is_timeout,source")?;
+        for dp in self {
+            let (kind, value, t, source) = flat_fields(dp);
+            writeln!(
+                f,
+                "{},{},{},{},{}",
+                t.duration_since(UNIX_EPOCH)?.as_millis(),
+                kind,
+                value.map(|v| v.to_string()).unwrap_or_default(),
+                value.is_none(),
+                csv_field(source)
+            )?;
+        }
+        f.flush()?;
         Ok(())
     }
 
-    fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
-        *self = serde_json::from_reader(File::open(path.as_ref())?)?;
-        Ok(())
+    fn duration(&self) -> Duration {
+        // merged logs or clock adjustments can leave the samples out of order
+        let first = self.iter().map(Datapoint::timestamp).min();
+        let last = self.iter().map(Datapoint::timestamp).max();
+        match (first, last) {
+            (Some(first), Some(last)) => last.duration_since(first).unwrap_or_default(),
+            _ => Duration::from_secs(0),
+        }
     }
+}
 
-    fn duration(&self) -> Duration {
-        if let Some(first) = self.first() {
-            if let Some(last) = self.last() {
-                match first {
-                    Datapoint::Latency(_, t)
-                    | Datapoint::ThroughputDown(_, t)
-                    | Datapoint::ThroughputUp(_, t) => match last {
-                        Datapoint::Latency(_, t2)
-                        | Datapoint::ThroughputDown(_, t2)
-                        | Datapoint::ThroughputUp(_, t2) => {
-                            if let Ok(dur) = t2.duration_since(*t) {
-                                return dur;
-                            }
-                        }
-                    },
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_means() {
+        let result: MeasurementResult = vec![];
+        assert_eq!(result.mean_dl(), 0.0);
+        assert_eq!(result.mean_ul(), 0.0);
+        assert_eq!(result.median_dl(), 0.0);
+        assert_eq!(result.mean_latency(), Duration::ZERO);
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(
+            csv_field("https://example.com/1M"),
+            "https://example.com/1M"
+        );
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn flat_kinds() {
+        let kind = |dp: &Datapoint| flat_fields(dp).0;
+        assert_eq!(kind(&Datapoint::add_tp_down(Some(50.))), "throughput_down");
+        // so per-url speeds are not mistaken for combined ones
+        let per_url =
+            Datapoint::add_tp_down_per_url("http://example.com".to_string(), Some(50.), None);
+        assert_eq!(kind(&per_url), "throughput_down_per_url");
+        assert_eq!(flat_fields(&per_url).3, "http://example.com");
+        assert_eq!(kind(&per_url.warmup()), "warmup_throughput_down_per_url");
+        let ping = Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(20)));
+        assert_eq!(
+            flat_fields(&ping.clone().warmup()),
+            ("warmup_latency", Some(20.), &ping.timestamp(), "8.8.8.8")
+        );
+    }
+
+    #[test]
+    fn moving_average() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(10))),
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(20))),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(60))),
+        ];
+        let averages = result
+            .latency_moving_average(2)
+            .into_iter()
+            .map(|(_, l)| l)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            averages,
+            vec![
+                Duration::from_millis(10),
+                Duration::from_millis(15),
+                Duration::from_millis(40)
+            ]
+        );
+        // a window larger than the sample count averages all samples so far
+        let last = result.latency_moving_average(100).last().unwrap().1;
+        assert_eq!(last, Duration::from_millis(30));
+
+        // targets are averaged separately
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("gateway", Some(Duration::from_millis(1))),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(21))),
+            Datapoint::add_latency("gateway", Some(Duration::from_millis(3))),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(23))),
+        ];
+        let averages = result
+            .latency_moving_average(2)
+            .into_iter()
+            .map(|(_, l)| l)
+            .collect::<Vec<_>>();
+        assert_eq!(averages, [1, 21, 2, 22].map(Duration::from_millis).to_vec());
+    }
+
+    #[test]
+    fn jitter_per_target() {
+        let mut result: MeasurementResult = vec![];
+        for _ in 0..10 {
+            result.push(Datapoint::add_latency(
+                "gateway",
+                Some(Duration::from_millis(1)),
+            ));
+            result.push(Datapoint::add_latency(
+                "8.8.8.8",
+                Some(Duration::from_millis(20)),
+            ));
+        }
+        assert!(result.jitter() < Duration::from_micros(1));
+        result.push(Datapoint::add_latency(
+            "gateway",
+            Some(Duration::from_millis(3)),
+        ));
+        // one change of 2 ms among 19 differences
+        let expected = Duration::from_secs_f64(0.002 / 19.);
+        assert!((result.jitter().as_secs_f64() - expected.as_secs_f64()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn jitter_and_stddev() {
+        let ping =
+            |ms: Option<u64>| Datapoint::add_latency("8.8.8.8", ms.map(Duration::from_millis));
+        // fewer than two samples have no variation
+        let empty: MeasurementResult = vec![];
+        assert_eq!(empty.jitter(), Duration::ZERO);
+        assert_eq!(empty.latency_stddev(), Duration::ZERO);
+        let single: MeasurementResult = vec![ping(Some(10)), ping(None)];
+        assert_eq!(single.jitter(), Duration::ZERO);
+        assert_eq!(single.latency_stddev(), Duration::ZERO);
+
+        // the timeout is skipped, the changes are 10 and 20 ms
+        let result: MeasurementResult =
+            vec![ping(Some(10)), ping(None), ping(Some(20)), ping(Some(40))];
+        assert_eq!(result.jitter(), Duration::from_millis(15));
+
+        let result: MeasurementResult = [2, 4, 4, 4, 5, 5, 7, 9]
+            .iter()
+            .map(|ms| ping(Some(*ms)))
+            .chain(std::iter::once(ping(None)))
+            .collect();
+        let stddev = result.latency_stddev().as_secs_f64() * 1000.;
+        assert!((stddev - 2.0).abs() < 1e-9, "{}", stddev);
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let ping =
+            |ms: Option<u64>| Datapoint::add_latency("8.8.8.8", ms.map(Duration::from_millis));
+        let empty: MeasurementResult = vec![];
+        assert_eq!(empty.latency_percentile(0.5), None);
+        let timeouts: MeasurementResult = vec![ping(None), ping(None)];
+        assert_eq!(timeouts.latency_p50(), None);
+
+        // unsorted, with a timeout that is skipped
+        let result: MeasurementResult =
+            vec![ping(Some(40)), ping(None), ping(Some(10)), ping(Some(20))];
+        assert_eq!(result.latency_p50(), Some(Duration::from_millis(20)));
+        // a quarter of the way between 10 and 20 ms
+        assert_eq!(
+            result.latency_percentile(0.125),
+            Some(Duration::from_micros(12_500))
+        );
+        // halfway between 20 and 40 ms
+        assert_eq!(
+            result.latency_percentile(0.75),
+            Some(Duration::from_millis(30))
+        );
+        // out of range percentiles are clamped
+        assert_eq!(
+            result.latency_percentile(-1.0),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            result.latency_percentile(2.0),
+            Some(Duration::from_millis(40))
+        );
+    }
+
+    #[test]
+    fn trends() {
+        let start = SystemTime::now();
+        let minutes = |m: u64| start + Duration::from_secs(m * 60);
+        let ping = |m: u64, ms: Option<u64>| {
+            Datapoint::latency_at("a", ms.map(Duration::from_millis), minutes(m))
+        };
+        let result: MeasurementResult = vec![
+            ping(0, Some(10)),
+            Datapoint::tp_down_at(Some(100.), minutes(0)),
+            ping(1, None),
+            ping(2, Some(14)),
+            Datapoint::tp_down_at(None, minutes(2)),
+            ping(4, Some(18)),
+            Datapoint::tp_down_at(Some(60.), minutes(4)),
+        ];
+        assert!((result.latency_trend() - 2.).abs() < 1e-9);
+        assert!((result.download_trend() - -10.).abs() < 1e-9);
+        assert_eq!(vec![ping(0, Some(10))].latency_trend(), 0.0);
+        assert_eq!(
+            vec![ping(0, Some(10)), ping(0, Some(20))].latency_trend(),
+            0.0
+        );
+        assert_eq!(MeasurementResult::new().download_trend(), 0.0);
+    }
+
+    #[test]
+    fn merge_interleaves() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let a = vec![
+            Datapoint::Latency("a".to_string(), None, at(0), None),
+            Datapoint::Latency("a".to_string(), None, at(2), None),
+        ];
+        let b = vec![
+            Datapoint::Latency("b".to_string(), None, at(1), None),
+            Datapoint::Latency("b".to_string(), None, at(3), None),
+        ];
+        let dir = std::env::temp_dir();
+        let files = vec![
+            dir.join("linetest_merge_a.ltst"),
+            dir.join("linetest_merge_b.ltst"),
+        ];
+        a.save(&files[0]).unwrap();
+        b.save(&files[1]).unwrap();
+
+        let merged = merge(&files).unwrap();
+        let order = merged.iter().map(Datapoint::timestamp).collect::<Vec<_>>();
+        assert_eq!(order, vec![at(0), at(1), at(2), at(3)]);
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn between_range() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let result: MeasurementResult = (0..4)
+            .map(|i| Datapoint::Latency("a".to_string(), None, at(i), None))
+            .collect();
+        let window = result.between(at(1), at(3));
+        assert_eq!(
+            window.iter().map(Datapoint::timestamp).collect::<Vec<_>>(),
+            vec![at(1), at(2)]
+        );
+        assert!(result.between(at(3), at(1)).is_empty());
+    }
+
+    #[test]
+    fn latency_histogram() {
+        let ms = |ms| Datapoint::add_latency("a", Some(Duration::from_millis(ms)));
+        let result: MeasurementResult = vec![
+            ms(3),
+            ms(12),
+            ms(15),
+            Datapoint::add_latency("a", None),
+            ms(31),
+            ms(5000),
+        ];
+        let histogram = result.latency_histogram(10.);
+        assert_eq!(histogram.len(), HISTOGRAM_BUCKETS);
+        assert_eq!(&histogram[..4], &[(0., 1), (10., 2), (20., 0), (30., 1)]);
+        assert_eq!(histogram.last(), Some(&(490., 1)));
+        assert_eq!(result[..3].to_vec().latency_histogram(10.).len(), 2);
+        assert!(MeasurementResult::new().latency_histogram(10.).is_empty());
+        assert!(result.latency_histogram(0.).is_empty());
+    }
+
+    #[test]
+    fn timeouts_ignore_throughput() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(10))),
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(12))),
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_tp_down(Some(50.)),
+            Datapoint::add_tp_down(None),
+            Datapoint::add_tp_up(Some(10.)),
+            Datapoint::add_latency_error("8.8.8.8", "Destination Host Unreachable"),
+        ];
+        assert_eq!(result.timeouts_for_session(), 0.5);
+        assert_eq!(MeasurementResult::new().timeouts_for_session(), 0.0);
+    }
+
+    #[test]
+    fn by_network() {
+        let ping = || Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(12)));
+        let groups = vec![ping(), ping()].by_network();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec![UNKNOWN_NETWORK]);
+
+        let result = vec![
+            ping(),
+            Datapoint::add_network(Some("home".to_string())),
+            ping(),
+            ping(),
+            Datapoint::add_network(Some("coffee shop".to_string())),
+            ping(),
+            Datapoint::add_network(None),
+            ping(),
+        ];
+        let groups = result.by_network();
+        assert_eq!(
+            groups.keys().collect::<Vec<_>>(),
+            vec!["coffee shop", "home", UNKNOWN_NETWORK]
+        );
+        assert_eq!(groups["home"].len(), 2);
+        assert_eq!(groups["coffee shop"].len(), 1);
+        assert_eq!(groups[UNKNOWN_NETWORK].len(), 2);
+    }
+
+    #[cfg(feature = "hdr")]
+    #[test]
+    fn latency_hdr() {
+        let mut result: MeasurementResult = (1..=1000)
+            .map(|ms| Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(ms))))
+            .collect();
+        result.push(Datapoint::add_latency("8.8.8.8", None));
+        result.push(Datapoint::add_latency(
+            "8.8.8.8",
+            Some(Duration::from_secs(3600)),
+        ));
+        let histogram = result.latency_hdr();
+        assert_eq!(histogram.len(), 1001);
+        let p50 = histogram.value_at_quantile(0.5) as f64;
+        let exact = result.latency_p50().unwrap().as_micros() as f64;
+        assert!((p50 - exact).abs() / exact < 0.001);
+        assert!(histogram.max() <= HDR_MAX_LATENCY.as_micros() as u64 * 1001 / 1000);
+    }
+
+    #[test]
+    fn availability() {
+        let answered = Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(12)));
+        let timeout = Datapoint::add_latency("8.8.8.8", None);
+        assert_eq!(MeasurementResult::new().availability(), 1.0);
+        assert_eq!(vec![answered.clone()].availability(), 1.0);
+        assert_eq!(MeasurementResult::new().availability_nines(), f64::INFINITY);
+        assert_eq!(vec![timeout.clone()].availability(), 0.0);
+        assert_eq!(vec![timeout.clone()].availability_nines(), 0.0);
+
+        let unreachable = Datapoint::add_latency_error("8.8.8.8", "Destination Host Unreachable");
+        assert_eq!(vec![unreachable.clone(); 3].availability(), 0.0);
+        assert_eq!(vec![answered.clone(), unreachable].availability(), 0.5);
+
+        let mut result = vec![answered; 999];
+        result.push(timeout);
+        assert!((result.availability() - 0.999).abs() < 1e-6);
+        assert!((result.availability_nines() - 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn prometheus_metrics() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(10))),
+            Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(20))),
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_tp_down(Some(50.5)),
+        ];
+        let metrics = result.to_prometheus();
+        assert!(metrics.contains("linetest_latency_ms{target=\"8.8.8.8\"} 20\n"));
+        assert!(metrics.contains("linetest_download_mbit 50.5\n"));
+        assert!(metrics.contains("linetest_timeouts_total 1\n"));
+        assert_eq!(prometheus_label("a\"b"), "a\\\"b");
+    }
+
+    #[test]
+    fn timeout_buckets() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let result: MeasurementResult = vec![
+            Datapoint::Latency("a".to_string(), None, at(0), None),
+            Datapoint::Latency(
+                "a".to_string(),
+                Some(Duration::from_millis(10)),
+                at(5),
+                None,
+            ),
+            Datapoint::ThroughputDown(None, at(6)),
+            // nothing recorded between 10 and 20
+            Datapoint::Latency(
+                "a".to_string(),
+                Some(Duration::from_millis(10)),
+                at(25),
+                None,
+            ),
+        ];
+        assert_eq!(
+            result.timeouts_by_bucket(Duration::from_secs(10)),
+            vec![(at(0), 1, 2), (at(10), 0, 0), (at(20), 0, 1)]
+        );
+        assert!(result.timeouts_by_bucket(Duration::ZERO).is_empty());
+        // a tiny bucket over a long measurement is widened
+        let result: MeasurementResult = vec![
+            Datapoint::Latency("a".to_string(), None, at(0), None),
+            Datapoint::Latency("a".to_string(), None, at(24 * 60 * 60), None),
+        ];
+        let buckets = result.timeouts_by_bucket(Duration::from_nanos(1));
+        assert!(buckets.len() <= MAX_TIMEOUT_BUCKETS);
+        assert_eq!(buckets.first().map(|b| (b.0, b.1)), Some((at(0), 1)));
+        assert_eq!(buckets.last().map(|b| b.1), Some(1));
+        assert!(buckets.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(MeasurementResult::new()
+            .timeouts_by_bucket(Duration::from_secs(10))
+            .is_empty());
+    }
+
+    #[test]
+    fn compressed_roundtrip() {
+        let result: MeasurementResult = (0..100)
+            .map(|_| Datapoint::add_latency("8.8.8.8", Some(Duration::from_millis(10))))
+            .collect();
+        let dir = std::env::temp_dir();
+        let plain = dir.join("linetest_compressed_roundtrip.ltst");
+        let compressed = dir.join("linetest_compressed_roundtrip.ltst.gz");
+        result.save(&plain).unwrap();
+        result.save(&compressed).unwrap();
+        // the temporary file is moved into place
+        assert!(!plain.with_extension("tmp").exists());
+        assert!(
+            std::fs::metadata(&compressed).unwrap().len()
+                < std::fs::metadata(&plain).unwrap().len()
+        );
+
+        for file in [plain, compressed] {
+            let mut loaded: MeasurementResult = vec![];
+            loaded.load(&file).unwrap();
+            assert_eq!(loaded.len(), result.len());
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn unsorted_duration() {
+        let start = SystemTime::now();
+        let ping =
+            |secs| Datapoint::Latency("a".into(), None, start + Duration::from_secs(secs), None);
+        let result = vec![ping(5), ping(0), ping(20), ping(10)];
+        assert_eq!(result.duration(), Duration::from_secs(20));
+        assert_eq!(vec![ping(5)].duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn summary() {
+        // one timestamp, so the trends are exactly 0 and survive the JSON round trip
+        let t = SystemTime::now();
+        let ping = |ms: Option<u64>| Datapoint::latency_at("a", ms.map(Duration::from_millis), t);
+        let result = vec![
+            ping(Some(10)),
+            ping(None),
+            ping(None),
+            ping(None),
+            ping(Some(30)),
+            Datapoint::tp_down_at(Some(20.), t),
+        ];
+        let summary = result.summary();
+        assert_eq!(summary.median_latency_ms, Some(20.));
+        assert_eq!(summary.timeouts, 3);
+        assert_eq!(summary.outages, 1);
+        assert_eq!(summary.max_download_mbit, 20.);
+        let json: Summary = serde_json::from_str(&result.summary_json()).unwrap();
+        assert_eq!(json, summary);
+        assert_eq!(MeasurementResult::new().summary().median_latency_ms, None);
+    }
+
+    #[test]
+    fn log_versions() {
+        let result = vec![Datapoint::add_latency(
+            "8.8.8.8",
+            Some(Duration::from_millis(10)),
+        )];
+        let path = std::env::temp_dir().join("linetest_log_versions.ltst");
+        result.save(&path).unwrap();
+        let saved: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], LOG_FORMAT_VERSION);
+
+        let mut loaded = vec![];
+        // logs of version 1 are a bare array
+        std::fs::write(&path, serde_json::to_vec(&result).unwrap()).unwrap();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        std::fs::write(&path, r#"{"version": 99, "datapoints": []}"#).unwrap();
+        assert!(loaded.load(&path).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn legacy_logs() {
+        let dir = std::env::temp_dir();
+        // written by the first release, before latencies named their ping target
+        let path = dir.join("linetest_legacy_baseline.ltst");
+        std::fs::write(&path, include_str!("../tests/fixtures/v1_baseline.ltst")).unwrap();
+        let mut loaded: MeasurementResult = vec![];
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 5);
+        assert_eq!(loaded.ping_targets(), vec![UNKNOWN_TARGET.to_string()]);
+        assert_eq!(loaded.timeouts(), 1);
+        assert_eq!(loaded.mean_dl(), 85.5);
+        assert_eq!(
+            loaded[0].timestamp(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        let _ = std::fs::remove_file(path);
+
+        // written before logs were versioned, per-url downloads without an HTTP version
+        let path = dir.join("linetest_legacy_targets.ltst");
+        std::fs::write(&path, include_str!("../tests/fixtures/v1_targets.ltst")).unwrap();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.ping_targets(), vec!["1.1.1.1".to_string()]);
+        assert!(matches!(loaded[0], Datapoint::Latency(_, _, _, Some(1))));
+        assert!(
+            matches!(&loaded[1], Datapoint::ThroughputDownPerUrl(url, Some(_), _, None) if url == "https://example.com/file")
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn jsonl_append() {
+        let path = std::env::temp_dir().join("linetest_jsonl_append.ltst");
+        let _ = std::fs::remove_file(&path);
+        for _ in 0..3 {
+            append_datapoint(&path, &Datapoint::add_latency("8.8.8.8", None)).unwrap();
         }
-        Duration::from_secs(0)
+        // simulate a crash in the middle of writing a line
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(b"{\"Latency\":[\"8.8.8").unwrap();
+
+        let mut loaded: MeasurementResult = vec![];
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.timeouts(), 3);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn binary_log() {
+        let result = vec![
+            Datapoint::add_sequenced_latency("8.8.8.8", Some(Duration::from_millis(12)), Some(3)),
+            Datapoint::add_latency_error("8.8.8.8", "Destination Host Unreachable"),
+            Datapoint::add_tp_down_per_url(
+                "http://example.com".to_string(),
+                Some(50.),
+                Some("HTTP/1.1".to_string()),
+            ),
+            Datapoint::add_tp_down(Some(50.)),
+            Datapoint::add_bytes_downloaded(1000),
+        ];
+        let json = std::env::temp_dir().join("linetest_binary_log.ltst");
+        let binary = std::env::temp_dir().join("linetest_binary_log.ltbin");
+        result.save(&json).unwrap();
+        result
+            .save_with_metadata(&binary, &Metadata::default())
+            .unwrap();
+        assert!(
+            std::fs::metadata(&binary).unwrap().len() < std::fs::metadata(&json).unwrap().len()
+        );
+
+        let mut from_json: MeasurementResult = vec![];
+        let mut from_binary: MeasurementResult = vec![];
+        from_json.load(&json).unwrap();
+        from_binary.load(&binary).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_json).unwrap(),
+            serde_json::to_value(&from_binary).unwrap()
+        );
+        assert_eq!(load_metadata(&binary).unwrap(), Some(Metadata::default()));
+        assert!(append_datapoint(&binary, &result[0]).is_err());
+
+        let newer = BinaryLogFile {
+            version: 99,
+            metadata: None,
+            datapoints: Vec::<u8>::new(),
+        };
+        std::fs::write(&binary, bincode::serialize(&newer).unwrap()).unwrap();
+        assert!(from_binary.load(&binary).is_err());
+        let _ = std::fs::remove_file(json);
+        let _ = std::fs::remove_file(binary);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn binary_log_v2() {
+        // written before per-url downloads recorded their HTTP version
+        let path = std::env::temp_dir().join("linetest_binary_log_v2.ltbin");
+        std::fs::write(&path, include_bytes!("../tests/fixtures/v2.ltbin")).unwrap();
+        let mut loaded: MeasurementResult = vec![];
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert!(matches!(&loaded[0], Datapoint::Network(Some(network), _) if network == "home"));
+        assert!(matches!(
+            &loaded[2],
+            Datapoint::ThroughputDownPerUrl(_, Some(_), _, None)
+        ));
+        assert_eq!(loaded.mean_dl(), 40.);
+        assert_eq!(
+            load_metadata(&path).unwrap().unwrap().label.as_deref(),
+            Some("v2")
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = Metadata {
+            hostname: Some("laptop".to_string()),
+            label: Some("coffee shop wifi".to_string()),
+            ..Default::default()
+        };
+        let result = vec![Datapoint::add_latency("8.8.8.8", None)];
+        let path = std::env::temp_dir().join("linetest_metadata.ltst");
+        result.save_with_metadata(&path, &metadata).unwrap();
+        assert_eq!(load_metadata(&path).unwrap(), Some(metadata.clone()));
+        let mut loaded: MeasurementResult = vec![];
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+        append_metadata(&path, &metadata).unwrap();
+        append_datapoint(&path, &result[0]).unwrap();
+        assert_eq!(load_metadata(&path).unwrap(), Some(metadata));
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        result.save(&path).unwrap();
+        assert_eq!(load_metadata(&path).unwrap(), None);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn outage_runs() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let ok = Some(Duration::from_millis(10));
+        let result: MeasurementResult = vec![
+            Datapoint::Latency("a".to_string(), None, at(0), None),
+            Datapoint::Latency("a".to_string(), ok, at(1), None),
+            Datapoint::Latency("a".to_string(), None, at(2), None),
+            Datapoint::ThroughputDown(None, at(3)),
+            Datapoint::Latency("a".to_string(), None, at(4), None),
+            Datapoint::Latency("a".to_string(), ok, at(5), None),
+            Datapoint::Latency("a".to_string(), None, at(6), None),
+            Datapoint::Latency("a".to_string(), None, at(7), None),
+        ];
+        assert_eq!(
+            result.outages(2),
+            vec![(at(2), at(5), 2), (at(6), at(7), 2)]
+        );
+        assert_eq!(result.outages(1).len(), 3);
+        assert!(result.outages(3).is_empty());
+
+        // a target that keeps answering doesn't end the outage of another one
+        let result: MeasurementResult = vec![
+            Datapoint::Latency("a".to_string(), None, at(0), None),
+            Datapoint::Latency("b".to_string(), ok, at(0), None),
+            Datapoint::Latency("a".to_string(), None, at(1), None),
+            Datapoint::Latency("b".to_string(), None, at(1), None),
+            Datapoint::Latency("a".to_string(), ok, at(2), None),
+            Datapoint::Latency("b".to_string(), ok, at(2), None),
+            Datapoint::Latency("b".to_string(), None, at(3), None),
+            Datapoint::Latency("a".to_string(), None, at(3), None),
+            Datapoint::Latency("b".to_string(), None, at(4), None),
+        ];
+        assert_eq!(
+            result.outages(2),
+            vec![(at(0), at(2), 2), (at(3), at(4), 2)]
+        );
+        assert_eq!(result.outages(1).len(), 4);
+    }
+
+    #[test]
+    fn series() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("a", Some(Duration::from_millis(10))),
+            Datapoint::add_tp_down(Some(30.)),
+            Datapoint::add_latency("b", None),
+            Datapoint::add_tp_up(Some(5.)),
+            Datapoint::add_tp_down(None),
+        ];
+        let latencies = result.latencies().map(|(_, l)| l).collect::<Vec<_>>();
+        assert_eq!(latencies, vec![Some(Duration::from_millis(10)), None]);
+        let downloads = result.downloads().map(|(_, dn)| dn).collect::<Vec<_>>();
+        assert_eq!(downloads, vec![Some(30.), None]);
+        assert_eq!(
+            result.downloads().next().map(|(t, _)| t),
+            Some(result[1].timestamp())
+        );
+    }
+
+    #[test]
+    fn quality_score() {
+        let ping = |ms| Datapoint::add_latency("a", Some(Duration::from_millis(ms)));
+        let good: MeasurementResult = (0..10).map(|_| ping(20)).collect();
+        assert!(good.quality_score() > 4.3);
+        let mut lossy = good.clone();
+        lossy.extend((0..2).map(|_| Datapoint::add_latency("a", None)));
+        assert!(lossy.quality_score() < good.quality_score());
+        let slow: MeasurementResult = (0..10).map(|i| ping(300 + i % 2 * 100)).collect();
+        assert!(slow.quality_score() < 3.5);
+        let lost: MeasurementResult = (0..5).map(|_| Datapoint::add_latency("a", None)).collect();
+        assert_eq!(lost.quality_score(), 1.0);
+        assert_eq!(MeasurementResult::new().quality_score(), 1.0);
+    }
+
+    #[test]
+    fn bufferbloat() {
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let ping = |ms, secs| {
+            Datapoint::Latency("a".into(), Some(Duration::from_millis(ms)), at(secs), None)
+        };
+        let mut result = vec![ping(20, 0), ping(20, 1), ping(20, 1)];
+        assert_eq!(result.bufferbloat_ms(), None);
+        // 10 MB at 20 Mbit/s take 4 s
+        result.extend([
+            ping(110, 3),
+            ping(100, 4),
+            ping(120, 5),
+            Datapoint::BytesDownloaded(10_000_000, at(6)),
+            Datapoint::ThroughputDown(Some(20.), at(6)),
+            ping(20, 7),
+        ]);
+        assert_eq!(result.bufferbloat_ms().map(|d| d.as_micros()), Some(90_000));
+    }
+
+    #[test]
+    fn compare() {
+        let ping = |ms| Datapoint::add_latency("a", Some(Duration::from_millis(ms)));
+        let before = vec![
+            ping(40),
+            ping(60),
+            Datapoint::add_latency("a", None),
+            ping(40),
+            Datapoint::add_tp_down(Some(10.)),
+        ];
+        let after = vec![ping(20), ping(20), Datapoint::add_tp_down(Some(50.))];
+        let comparison = before.compare(&after);
+        assert!((comparison.mean_latency_ms - -26.67).abs() < 0.01);
+        assert!(comparison.jitter_ms < 0.0);
+        assert_eq!(comparison.download_mbit, 40.);
+        assert_eq!(comparison.timeout_rate, -0.25);
+        assert_eq!(after.compare(&after), Comparison::default());
+    }
+
+    #[test]
+    fn downsample() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut result: MeasurementResult = (0..1000)
+            .map(|i| {
+                Datapoint::Latency(
+                    "a".to_string(),
+                    Some(Duration::from_millis(20 + i % 7)),
+                    at(i),
+                    None,
+                )
+            })
+            .collect();
+        result[500] = Datapoint::Latency(
+            "a".to_string(),
+            Some(Duration::from_millis(900)),
+            at(500),
+            None,
+        );
+        result[700] = Datapoint::Latency("a".to_string(), None, at(700), None);
+        result.push(Datapoint::ThroughputDown(Some(50.), at(1000)));
+
+        let reduced = result.downsample(100);
+        assert!(reduced.len() <= 101);
+        assert!(reduced
+            .windows(2)
+            .all(|w| w[0].timestamp() < w[1].timestamp()));
+        // spikes, timeouts and short series survive
+        assert_eq!(
+            reduced.latency_percentile(1.0),
+            Some(Duration::from_millis(900))
+        );
+        assert_eq!(reduced.timeouts(), 1);
+        assert_eq!(reduced.downloads().count(), 1);
+        assert_eq!(result[..10].to_vec().downsample(100).len(), 10);
+    }
+
+    #[test]
+    fn reordered_pings() {
+        let ok = Some(Duration::from_millis(10));
+        let result: MeasurementResult = vec![
+            Datapoint::add_sequenced_latency("a", ok, Some(1)),
+            Datapoint::add_sequenced_latency("a", ok, Some(3)),
+            Datapoint::add_sequenced_latency("b", ok, Some(2)),
+            Datapoint::add_sequenced_latency("a", ok, Some(2)),
+            Datapoint::add_sequenced_latency("a", None, Some(1)),
+            Datapoint::add_latency("a", ok),
+            Datapoint::add_sequenced_latency("a", ok, Some(65535)),
+            Datapoint::add_sequenced_latency("a", ok, Some(0)),
+        ];
+        assert_eq!(result.reordered_count(), 1);
+
+        // logs from before sequence numbers were recorded
+        let old = r#"{"Latency":["a",null,{"secs_since_epoch":1,"nanos_since_epoch":0}]}"#;
+        let dp: Datapoint = serde_json::from_str(old).unwrap();
+        assert!(matches!(dp, Datapoint::Latency(_, None, _, None)));
+    }
+
+    #[test]
+    fn download_range() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_tp_down(Some(30.)),
+            Datapoint::add_tp_down(None),
+            Datapoint::add_tp_down(Some(10.)),
+            Datapoint::add_tp_down(Some(40.)),
+            Datapoint::add_tp_down(Some(20.)),
+        ];
+        assert_eq!(result.min_dl(), 10.);
+        assert_eq!(result.max_dl(), 40.);
+        assert_eq!(result.median_dl(), 25.);
+    }
+
+    #[test]
+    fn bytes_downloaded() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_bytes_downloaded(1000),
+            Datapoint::add_tp_down(Some(8.)),
+            Datapoint::add_bytes_downloaded(500),
+            Datapoint::add_tp_down(None),
+        ];
+        assert_eq!(result.total_bytes_downloaded(), 1500);
+        // data volume doesn't count as a throughput sample
+        assert_eq!(result.mean_dl(), 4.);
+    }
+
+    #[test]
+    fn all_timeout_means() {
+        let result: MeasurementResult = vec![
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_latency("8.8.8.8", None),
+            Datapoint::add_latency("8.8.8.8", None),
+        ];
+        assert_eq!(result.mean_dl(), 0.0);
+        assert_eq!(result.mean_latency(), Duration::ZERO);
+        assert_eq!(result.timeouts(), 3);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    any::Any,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use super::Datapoint;
+
+/// A handle to a running measurement. Incoming [Datapoint]s can be read
+/// from it like from a `Receiver`. The measurement is stopped when [MeasurementHandle::stop]
+/// is called or the handle is dropped.
+#[derive(Debug)]
+pub struct MeasurementHandle {
+    receiver: Receiver<Datapoint>,
+    stop_flag: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+    /// Why the background thread failed, once it has been joined
+    failure: Option<String>,
+}
+
+impl MeasurementHandle {
+    /// A handle to the measurement of `thread`, which returns why the measurement failed
+    /// if it did, e.g. to pass on the failure of a measurement it wraps
+    pub(crate) fn new(
+        receiver: Receiver<Datapoint>,
+        stop_flag: Arc<AtomicBool>,
+        thread: JoinHandle<Result<(), String>>,
+    ) -> Self {
+        Self {
+            receiver,
+            stop_flag,
+            thread: Some(thread),
+            failure: None,
+        }
+    }
+
+    /// The flag that stops the measurement when set
+    pub(crate) fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop_flag.clone()
+    }
+
+    /// Stop the measurement and wait for the background thread to finish
+    pub fn stop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        self.join();
+    }
+
+    /// Stop the measurement like [MeasurementHandle::stop] and return its failure, e.g.
+    /// to pass it on from a thread wrapping the measurement
+    pub(crate) fn conclude(&mut self) -> Result<(), String> {
+        self.stop();
+        self.failure().map_or(Ok(()), Err)
+    }
+
+    /// Join the background thread and keep the reason if it failed or panicked
+    fn join(&mut self) {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(failure))) => self.failure = Some(failure),
+            Some(Err(panic)) => self.failure = Some(panic_message(panic.as_ref())),
+            Some(Ok(Ok(()))) | None => (),
+        }
+    }
+
+    /// Why the measurement failed, if its background thread returned an error or panicked.
+    /// `None` while it is running or after it ended normally.
+    pub fn failure(&mut self) -> Option<String> {
+        if self
+            .thread
+            .as_ref()
+            .map(JoinHandle::is_finished)
+            .unwrap_or_default()
+        {
+            self.join();
+        }
+        self.failure.clone()
+    }
+
+    /// Returns true if the measurement has neither been stopped nor concluded
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .map(|t| !t.is_finished())
+            .unwrap_or_default()
+    }
+}
+
+impl Deref for MeasurementHandle {
+    type Target = Receiver<Datapoint>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+/// The message a thread panicked with
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = panic.downcast_ref::<String>() {
+        return message.clone();
+    }
+    "unknown error".to_string()
+}
+
+impl Drop for MeasurementHandle {
+    fn drop(&mut self) {
+        // Don't block on drop, the thread exits on its next check of the flag
+        self.stop_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Pass the datapoints of `measurement` on to the returned handle. The ones measured
+/// within `warmup` from now are marked as [Datapoint::Warmup].
+pub(crate) fn mark_warmup(
+    mut measurement: MeasurementHandle,
+    warmup: Duration,
+) -> MeasurementHandle {
+    let stop_flag = measurement.stop_flag();
+    let warmup_end = SystemTime::now() + warmup;
+    let (sender, receiver) = channel();
+
+    let thread = thread::spawn(move || {
+        for dp in measurement.iter() {
+            let dp = if dp.timestamp() < warmup_end {
+                dp.warmup()
+            } else {
+                dp
+            };
+            if sender.send(dp).is_err() {
+                break;
+            }
+        }
+        measurement.conclude()
+    });
+
+    MeasurementHandle::new(receiver, stop_flag, thread)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Evaluation, MeasurementResult};
+
+    #[test]
+    fn failure() {
+        let (_sender, receiver) = channel();
+        let thread = thread::spawn(|| panic!("no ping"));
+        let mut handle = MeasurementHandle::new(receiver, Arc::new(AtomicBool::new(false)), thread);
+        while handle.is_running() {
+            thread::yield_now();
+        }
+        assert_eq!(handle.failure().as_deref(), Some("no ping"));
+
+        let (_sender, receiver) = channel();
+        let mut handle = MeasurementHandle::new(
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            thread::spawn(|| Ok(())),
+        );
+        handle.stop();
+        assert_eq!(handle.failure(), None);
+
+        // a wrapper passes the failure on without panicking itself
+        let (_, receiver) = channel();
+        let handle = MeasurementHandle::new(
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            thread::spawn(|| Err("no route".to_string())),
+        );
+        let mut handle = mark_warmup(handle, Duration::ZERO);
+        assert_eq!(handle.conclude(), Err("no route".to_string()));
+    }
+
+    #[test]
+    fn warmup() {
+        let (sender, receiver) = channel();
+        let handle = MeasurementHandle::new(
+            receiver,
+            Arc::new(AtomicBool::new(false)),
+            thread::spawn(|| Ok(())),
+        );
+        let handle = mark_warmup(handle, Duration::from_secs(60));
+        let later = SystemTime::now() + Duration::from_secs(120);
+        sender
+            .send(Datapoint::add_latency(
+                "a",
+                Some(Duration::from_millis(500)),
+            ))
+            .unwrap();
+        sender.send(Datapoint::add_network(None)).unwrap();
+        sender
+            .send(Datapoint::Latency(
+                "a".to_string(),
+                Some(Duration::from_millis(20)),
+                later,
+                None,
+            ))
+            .unwrap();
+        drop(sender);
+
+        let result: MeasurementResult = handle.iter().collect();
+        assert_eq!(
+            result.iter().map(Datapoint::is_warmup).collect::<Vec<_>>(),
+            [true, false, false]
+        );
+        assert!(result[0].measurement().is_latency());
+        // the warm-up ping is recorded, but doesn't count
+        assert_eq!(result.latencies().count(), 1);
+        assert_eq!(result.mean_latency(), Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,493 @@
+use crate::latency::HopResult;
+use anyhow::Error;
+use log::debug;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    io::{self, ErrorKind, Read},
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const UNREACHABLE_V4: u8 = 3;
+const TIME_EXCEEDED_V4: u8 = 11;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+const UNREACHABLE_V6: u8 = 1;
+const TIME_EXCEEDED_V6: u8 = 3;
+
+/// Length of an ICMP echo header: type, code, checksum, identifier and sequence number
+const HEADER_SIZE: usize = 8;
+
+/// Length of the echo request payload, the same as the ping binary sends
+const PAYLOAD_SIZE: usize = 56;
+
+/// Length of the header of an IPv6 packet quoted in an error message
+const IPV6_HEADER_SIZE: usize = 40;
+
+/// How long a reply may take before its request counts as timed out, unless the ping interval
+/// is longer. Like the default of the Windows ping, so replies delayed by a loaded line still count.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Counts the pingers of this process, so each sends with its own identifier
+static PINGERS: AtomicU16 = AtomicU16::new(0);
+
+/// What an incoming ICMP packet says about one of our echo requests
+#[derive(Debug, PartialEq)]
+enum Reply {
+    /// The echo reply with this sequence number
+    Echo(u16),
+    /// The echo request with this sequence number could not be delivered
+    Unreachable(u16),
+    /// A router dropped the echo request with this sequence number as its time to live ran out
+    TimeExceeded(u16),
+}
+
+impl Reply {
+    fn sequence(&self) -> u16 {
+        match self {
+            Reply::Echo(s) | Reply::Unreachable(s) | Reply::TimeExceeded(s) => *s,
+        }
+    }
+}
+
+/// Sends ICMP echo requests to one address from this process, without the system ping binary
+pub struct IcmpPinger {
+    socket: Socket,
+    addr: IpAddr,
+    /// Raw sockets receive all ICMP packets, datagram sockets only the replies to their own requests
+    raw: bool,
+    identifier: u16,
+}
+
+impl IcmpPinger {
+    /// Open an ICMP socket to ping `addr`. Unprivileged datagram sockets are tried first, then
+    /// raw sockets. Fails if the process is allowed to open neither.
+    pub fn new(addr: IpAddr) -> io::Result<Self> {
+        Self::open(addr, Type::DGRAM).or_else(|e| {
+            debug!(
+                "Can't open an ICMP datagram socket ({}), trying a raw socket",
+                e
+            );
+            Self::new_raw(addr)
+        })
+    }
+
+    /// Open a raw ICMP socket to ping `addr`. Unlike datagram sockets, it also receives the
+    /// errors routers send back, as needed by [IcmpPinger::traceroute]. This usually needs root.
+    pub fn new_raw(addr: IpAddr) -> io::Result<Self> {
+        Self::open(addr, Type::RAW)
+    }
+
+    fn open(addr: IpAddr, ty: Type) -> io::Result<Self> {
+        let (domain, protocol) = match addr {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        Ok(Self {
+            socket: Socket::new(domain, ty, Some(protocol))?,
+            addr,
+            raw: ty == Type::RAW,
+            // raw sockets of several pingers see each other's replies, tell them apart
+            identifier: (std::process::id() as u16)
+                .wrapping_add(PINGERS.fetch_add(1, Ordering::Relaxed)),
+        })
+    }
+
+    /// Send an echo request every `interval` and call `callback` for each reply or timeout,
+    /// like [crate::latency::ping_callback]. A request counts as timed out if no reply arrives
+    /// within [REPLY_TIMEOUT] or `interval`, whichever is longer. Requests are sent on time
+    /// while earlier ones still wait for their reply, and results are passed on as they arrive.
+    pub fn ping_callback<F: FnMut(u64, Result<Option<Duration>, String>) -> bool>(
+        &self,
+        interval: Duration,
+        count: Option<usize>,
+        mut callback: F,
+    ) -> Result<(), Error> {
+        let target = SockAddr::from(SocketAddr::new(self.addr, 0));
+        let timeout = interval.max(REPLY_TIMEOUT);
+        // the sequence number and send time of the requests waiting for a reply, by the
+        // sequence number on the wire, which wraps around
+        let mut pending: BTreeMap<u16, (u64, Instant)> = BTreeMap::new();
+        let mut results = vec![];
+        let mut sequence: u64 = 0;
+        let mut next_request = Instant::now();
+        let mut delivered = 0;
+        loop {
+            let sending = count.map(|c| (sequence as usize) < c).unwrap_or(true);
+            if sending && Instant::now() >= next_request {
+                let sent = Instant::now();
+                match self
+                    .socket
+                    .send_to(&self.echo_request(sequence as u16), &target)
+                {
+                    Ok(_) => {
+                        pending.insert(sequence as u16, (sequence, sent));
+                    }
+                    // e.g. no route to the host
+                    Err(e) => results.push((sequence, Err(e.to_string()))),
+                }
+                sequence += 1;
+                next_request = sent + interval;
+            }
+
+            let now = Instant::now();
+            pending.retain(|_, (sequence, sent)| {
+                let waiting = now.duration_since(*sent) < timeout;
+                if !waiting {
+                    results.push((*sequence, Ok(None)));
+                }
+                waiting
+            });
+
+            // wait for replies until the next request or timeout is due
+            let deadline = pending
+                .values()
+                .map(|(_, sent)| *sent + timeout)
+                .chain(sending.then_some(next_request))
+                .min();
+            if let Some(deadline) = deadline {
+                match self.receive_any(deadline) {
+                    Ok(Some((reply, _, received))) => {
+                        if let Some((sequence, sent)) = pending.remove(&reply.sequence()) {
+                            let rtt = received.duration_since(sent);
+                            results.push((sequence, self.reply_result(reply, rtt)));
+                        }
+                    }
+                    Ok(None) => (),
+                    // e.g. an error about the oldest request reported by the socket
+                    Err(e) => {
+                        if let Some((&wire_sequence, &(sequence, _))) =
+                            pending.iter().min_by_key(|(_, (sequence, _))| *sequence)
+                        {
+                            pending.remove(&wire_sequence);
+                            results.push((sequence, Err(e.to_string())));
+                        }
+                    }
+                }
+            }
+
+            for (sequence, result) in results.drain(..) {
+                let keep_going = callback(sequence, result);
+                delivered += 1;
+                if !keep_going || count.map(|c| delivered >= c).unwrap_or_default() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Send echo requests with a time to live of 1, 2, 3 and so on, so each router on the way
+    /// reports back in turn, until the address itself replies or `max_hops` is reached.
+    /// Each router gets `timeout` to answer.
+    pub fn traceroute(&self, max_hops: u32, timeout: Duration) -> io::Result<Vec<HopResult>> {
+        let target = SockAddr::from(SocketAddr::new(self.addr, 0));
+        let mut hops = vec![];
+        for ttl in 1..=max_hops {
+            match self.addr {
+                IpAddr::V4(_) => self.socket.set_ttl(ttl)?,
+                IpAddr::V6(_) => self.socket.set_unicast_hops_v6(ttl)?,
+            }
+            let sequence = ttl as u16;
+            let sent = Instant::now();
+            self.socket.send_to(&self.echo_request(sequence), &target)?;
+            let (hop, done) = match self.receive(sequence, sent, timeout)? {
+                Some((reply, address, rtt)) => (
+                    HopResult {
+                        ttl,
+                        address,
+                        rtt: Some(rtt),
+                    },
+                    !matches!(reply, Reply::TimeExceeded(_)),
+                ),
+                None => (
+                    HopResult {
+                        ttl,
+                        address: None,
+                        rtt: None,
+                    },
+                    false,
+                ),
+            };
+            hops.push(hop);
+            if done {
+                break;
+            }
+        }
+        Ok(hops)
+    }
+
+    /// An echo request with the given sequence number
+    fn echo_request(&self, sequence: u16) -> Vec<u8> {
+        let mut packet = vec![0; HEADER_SIZE + PAYLOAD_SIZE];
+        packet[0] = match self.addr {
+            IpAddr::V4(_) => ECHO_REQUEST_V4,
+            IpAddr::V6(_) => ECHO_REQUEST_V6,
+        };
+        packet[4..6].copy_from_slice(&self.identifier.to_be_bytes());
+        packet[6..8].copy_from_slice(&sequence.to_be_bytes());
+        for (i, byte) in packet[HEADER_SIZE..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        // the kernel fills in the checksum of ICMPv6 packets
+        if self.addr.is_ipv4() {
+            let sum = checksum(&packet);
+            packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        packet
+    }
+
+    /// The result of a ping answered by `reply` after `rtt`: the round trip time of an echo
+    /// reply, or `Err` if the request could not be delivered
+    fn reply_result(&self, reply: Reply, rtt: Duration) -> Result<Option<Duration>, String> {
+        match reply {
+            Reply::Echo(_) => Ok(Some(rtt)),
+            Reply::Unreachable(_) => Err(format!("Destination {} unreachable", self.addr)),
+            Reply::TimeExceeded(_) => {
+                Err(format!("Time to live exceeded on the way to {}", self.addr))
+            }
+        }
+    }
+
+    /// Wait until `timeout` after `sent` for a packet about the request with the given `sequence`.
+    /// Returns the packet, who sent it and the round trip time, or `None` on timeout.
+    fn receive(
+        &self,
+        sequence: u16,
+        sent: Instant,
+        timeout: Duration,
+    ) -> io::Result<Option<(Reply, Option<IpAddr>, Duration)>> {
+        loop {
+            match self.receive_any(sent + timeout)? {
+                Some((reply, address, received)) if reply.sequence() == sequence => {
+                    return Ok(Some((reply, address, received.duration_since(sent))));
+                }
+                // late replies to earlier requests
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Wait until `deadline` for a packet about any of our requests. Returns the packet,
+    /// who sent it and when it arrived, or `None` on timeout.
+    fn receive_any(
+        &self,
+        deadline: Instant,
+    ) -> io::Result<Option<(Reply, Option<IpAddr>, Instant)>> {
+        let mut buffer = [0; 1500];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let received = self
+                .socket
+                .peek_sender()
+                .and_then(|sender| Ok((sender, (&self.socket).read(&mut buffer)?)));
+            let (sender, len) = match received {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let address = sender.as_socket().map(|addr| addr.ip());
+            // packets of other pingers are skipped
+            if let Some(reply) = self.parse(&buffer[..len], address) {
+                return Ok(Some((reply, address, Instant::now())));
+            }
+        }
+    }
+
+    /// Read an incoming ICMP packet from `sender`. IPv4 packets may start with the IP header.
+    /// Only echo replies sent by the pinged address and errors about requests to it are read.
+    fn parse(&self, packet: &[u8], sender: Option<IpAddr>) -> Option<Reply> {
+        let (reply, unreachable, time_exceeded) = match self.addr {
+            IpAddr::V4(_) => (ECHO_REPLY_V4, UNREACHABLE_V4, TIME_EXCEEDED_V4),
+            IpAddr::V6(_) => (ECHO_REPLY_V6, UNREACHABLE_V6, TIME_EXCEEDED_V6),
+        };
+        let icmp = match self.addr {
+            IpAddr::V4(_) => strip_ipv4_header(packet)?,
+            IpAddr::V6(_) => packet,
+        };
+        let header = icmp.get(..HEADER_SIZE)?;
+        if header[0] == reply {
+            // datagram sockets replace the identifier with their own and only see their replies
+            if self.raw && header[4..6] != self.identifier.to_be_bytes() {
+                return None;
+            }
+            if sender != Some(self.addr) {
+                return None;
+            }
+            return Some(Reply::Echo(u16::from_be_bytes([header[6], header[7]])));
+        }
+        if header[0] == unreachable || header[0] == time_exceeded {
+            // the error comes from a router and quotes the IP header and echo header of the
+            // request that failed
+            let quoted = &icmp[HEADER_SIZE..];
+            let (destination, request) = match self.addr {
+                IpAddr::V4(_) => {
+                    let destination: [u8; 4] = quoted.get(16..20)?.try_into().ok()?;
+                    (IpAddr::from(destination), strip_ipv4_header(quoted)?)
+                }
+                IpAddr::V6(_) => {
+                    let destination: [u8; 16] =
+                        quoted.get(24..IPV6_HEADER_SIZE)?.try_into().ok()?;
+                    (IpAddr::from(destination), quoted.get(IPV6_HEADER_SIZE..)?)
+                }
+            };
+            if destination != self.addr {
+                return None;
+            }
+            let request = request.get(..HEADER_SIZE)?;
+            if self.raw && request[4..6] != self.identifier.to_be_bytes() {
+                return None;
+            }
+            let sequence = u16::from_be_bytes([request[6], request[7]]);
+            if header[0] == time_exceeded {
+                return Some(Reply::TimeExceeded(sequence));
+            }
+            return Some(Reply::Unreachable(sequence));
+        }
+        None
+    }
+}
+
+/// Skip the IPv4 header if the packet starts with one. ICMP messages never
+/// start with 0x4_, as there is no such ICMP type.
+fn strip_ipv4_header(packet: &[u8]) -> Option<&[u8]> {
+    match packet.first() {
+        Some(first) if first >> 4 == 4 => packet.get(usize::from(first & 0x0f) * 4..),
+        Some(_) => Some(packet),
+        None => None,
+    }
+}
+
+/// The internet checksum (RFC 1071) of `data`
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn echo_packets() {
+        let pinger = IcmpPinger {
+            socket: Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap(),
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            raw: true,
+            identifier: 0x1234,
+        };
+        let request = pinger.echo_request(7);
+        assert_eq!(&request[..2], &[ECHO_REQUEST_V4, 0]);
+        // a packet including its checksum sums up to 0
+        assert_eq!(checksum(&request), 0);
+
+        let localhost = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mut reply = request.clone();
+        reply[0] = ECHO_REPLY_V4;
+        assert_eq!(pinger.parse(&reply, localhost), Some(Reply::Echo(7)));
+        let mut with_ip_header = vec![0x45];
+        with_ip_header.extend([0; 19]);
+        with_ip_header.extend(&reply);
+        assert_eq!(
+            pinger.parse(&with_ip_header, localhost),
+            Some(Reply::Echo(7))
+        );
+        // the reply of another target
+        assert_eq!(
+            pinger.parse(&reply, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))),
+            None
+        );
+        reply[4] = 0;
+        assert_eq!(pinger.parse(&reply, localhost), None);
+
+        // quotes the IP header of the request to 127.0.0.1
+        let router = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)));
+        let mut unreachable = vec![UNREACHABLE_V4, 1, 0, 0, 0, 0, 0, 0, 0x45];
+        unreachable.extend([0; 15]);
+        unreachable.extend([127, 0, 0, 1]);
+        unreachable.extend(&request);
+        assert_eq!(
+            pinger.parse(&unreachable, router),
+            Some(Reply::Unreachable(7))
+        );
+        unreachable[0] = TIME_EXCEEDED_V4;
+        assert_eq!(
+            pinger.parse(&unreachable, router),
+            Some(Reply::TimeExceeded(7))
+        );
+        // about a request to another target
+        unreachable[8 + 19] = 2;
+        assert_eq!(pinger.parse(&unreachable, router), None);
+    }
+
+    #[test]
+    fn unique_identifiers() {
+        let first = IcmpPinger::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let second = IcmpPinger::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        if let (Ok(first), Ok(second)) = (first, second) {
+            assert_ne!(first.identifier, second.identifier);
+        }
+    }
+
+    #[test]
+    fn ping_localhost() {
+        let pinger = match IcmpPinger::new(IpAddr::V4(Ipv4Addr::LOCALHOST)) {
+            Ok(pinger) => pinger,
+            // not allowed on this system
+            Err(_) => return,
+        };
+        let mut results = vec![];
+        pinger
+            .ping_callback(Duration::from_millis(100), Some(3), |_, res| {
+                results.push(res);
+                true
+            })
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|res| matches!(res, Ok(Some(_)))));
+
+        // requests are sent on time, however long the replies take
+        let mut sequences = vec![];
+        pinger
+            .ping_callback(Duration::from_millis(1), Some(20), |sequence, res| {
+                assert!(matches!(res, Ok(Some(_))));
+                sequences.push(sequence);
+                true
+            })
+            .unwrap();
+        sequences.sort_unstable();
+        assert_eq!(sequences, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn traceroute_localhost() {
+        let pinger = match IcmpPinger::new_raw(IpAddr::V4(Ipv4Addr::LOCALHOST)) {
+            Ok(pinger) => pinger,
+            // not allowed on this system
+            Err(_) => return,
+        };
+        let hops = pinger.traceroute(5, Duration::from_millis(500)).unwrap();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].address, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert!(hops[0].rtt.is_some());
+    }
+}
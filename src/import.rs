@@ -0,0 +1,350 @@
+use anyhow::{anyhow, Error};
+use chrono::DateTime;
+use serde_json::Value;
+use std::{
+    convert::TryInto,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    latency::{ping_error, sequence_number},
+    Datapoint, MeasurementResult,
+};
+
+/// The spacing of imported pings without a timestamp, the default interval of `ping`
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The spacing of imported speedtests without a timestamp
+const SPEEDTEST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The ping target of imported speedtests that don't name their server
+const SPEEDTEST_TARGET: &str = "speedtest";
+
+/// Read the output of the `ping` command into latency datapoints. Lines printed with
+/// `ping -D` keep their timestamps, the others are spaced a second apart, ending now.
+pub fn import_ping_log(text: &str) -> Result<MeasurementResult, Error> {
+    let mut target = None;
+    let mut pings = vec![];
+    for line in text.lines() {
+        let (timestamp, line) = match split_timestamp(line.trim()) {
+            Some(split) => split,
+            // e.g. a timestamp too far in the future
+            None => continue,
+        };
+        if let Some(header) = line
+            .strip_prefix("PING ")
+            .or_else(|| line.strip_prefix("Pinging "))
+        {
+            target = header.split_whitespace().next().map(str::to_string);
+            continue;
+        }
+        let lower = line.to_lowercase();
+        let result = if let Some(error) = ping_error(line) {
+            Err(error)
+        } else if let Some(latency) = ping_time(line) {
+            Ok(Some(latency))
+        } else if lower.contains("timeout")
+            || lower.contains("timed out")
+            || lower.contains("no answer")
+        {
+            Ok(None)
+        } else {
+            continue;
+        };
+        pings.push((timestamp, sequence_number(line), result));
+    }
+    if pings.is_empty() {
+        return Err(anyhow!("No ping results found"));
+    }
+
+    let target = target.unwrap_or_else(|| "imported".to_string());
+    let timestamps = fill_timestamps(pings.iter().map(|(t, ..)| *t).collect(), PING_INTERVAL);
+    Ok(pings
+        .into_iter()
+        .zip(timestamps)
+        .map(|((_, sequence, result), t)| match result {
+            Ok(latency) => Datapoint::Latency(target.clone(), latency, t, sequence),
+            Err(error) => Datapoint::LatencyError(target.clone(), error, t),
+        })
+        .collect())
+}
+
+/// Read the JSON results of speedtest tools into latency and throughput datapoints.
+/// Understands the Ookla `speedtest --format=json`, `speedtest-cli --json` and
+/// `fast --json` output, as a single result, an array or one result per line.
+/// Results without a timestamp are spaced a minute apart, ending now.
+pub fn import_speedtest_json(text: &str) -> Result<MeasurementResult, Error> {
+    let results = match serde_json::from_str::<Value>(text) {
+        Ok(Value::Array(results)) => results,
+        Ok(result) => vec![result],
+        Err(_) => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?,
+    };
+    let results = results
+        .iter()
+        .filter_map(SpeedtestResult::from_json)
+        .collect::<Vec<_>>();
+    if results.is_empty() {
+        return Err(anyhow!("No speedtest results found"));
+    }
+
+    let timestamps = fill_timestamps(
+        results.iter().map(|r| r.timestamp).collect(),
+        SPEEDTEST_INTERVAL,
+    );
+    let mut imported = MeasurementResult::new();
+    for (result, t) in results.into_iter().zip(timestamps) {
+        if let Some(latency) = result.latency {
+            imported.push(Datapoint::latency_at(&result.server, Some(latency), t));
+        }
+        if let Some(download) = result.download {
+            imported.push(Datapoint::tp_down_at(Some(download), t));
+        }
+        if let Some(upload) = result.upload {
+            imported.push(Datapoint::tp_up_at(Some(upload), t));
+        }
+    }
+    Ok(imported)
+}
+
+/// Read the output of another measurement tool, JSON speedtest results with
+/// [import_speedtest_json] and anything else with [import_ping_log]
+pub fn import(text: &str) -> Result<MeasurementResult, Error> {
+    if text.trim_start().starts_with(['{', '[']) {
+        import_speedtest_json(text)
+    } else {
+        import_ping_log(text)
+    }
+}
+
+/// The result of one speedtest, speeds in Mbit/s
+struct SpeedtestResult {
+    timestamp: Option<SystemTime>,
+    server: String,
+    latency: Option<Duration>,
+    download: Option<f32>,
+    upload: Option<f32>,
+}
+
+impl SpeedtestResult {
+    fn from_json(json: &Value) -> Option<Self> {
+        let number = |pointer: &str| json.pointer(pointer).and_then(Value::as_f64);
+        let result = if json.get("type").and_then(Value::as_str) == Some("result") {
+            // Ookla, bandwidth in bytes per second
+            Self {
+                timestamp: timestamp(json),
+                server: server(json),
+                latency: number("/ping/latency").and_then(ms),
+                download: number("/download/bandwidth").map(|b| (b * 8. / 1_000_000.) as f32),
+                upload: number("/upload/bandwidth").map(|b| (b * 8. / 1_000_000.) as f32),
+            }
+        } else if json.get("downloadSpeed").is_some() {
+            // fast.com, already in Mbit/s
+            Self {
+                timestamp: timestamp(json),
+                server: SPEEDTEST_TARGET.to_string(),
+                latency: number("/latency").and_then(ms),
+                download: number("/downloadSpeed").map(|s| s as f32),
+                upload: number("/uploadSpeed").map(|s| s as f32),
+            }
+        } else {
+            // speedtest-cli, speeds in bit per second
+            Self {
+                timestamp: timestamp(json),
+                server: server(json),
+                latency: number("/ping").and_then(ms),
+                download: number("/download").map(|b| (b / 1_000_000.) as f32),
+                upload: number("/upload").map(|b| (b / 1_000_000.) as f32),
+            }
+        };
+        (result.latency.is_some() || result.download.is_some() || result.upload.is_some())
+            .then_some(result)
+    }
+}
+
+/// A duration of `ms` milliseconds, `None` if it is too long to be one
+fn ms(ms: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(ms.max(0.0) / 1000.).ok()
+}
+
+/// The RFC 3339 timestamp of a speedtest result
+fn timestamp(json: &Value) -> Option<SystemTime> {
+    let timestamp = DateTime::parse_from_rfc3339(json.get("timestamp")?.as_str()?).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_millis(
+        timestamp.timestamp_millis().try_into().ok()?,
+    ))
+}
+
+/// The host of the server a speedtest result was measured against
+fn server(json: &Value) -> String {
+    json.pointer("/server/host")
+        .and_then(Value::as_str)
+        .unwrap_or(SPEEDTEST_TARGET)
+        .to_string()
+}
+
+/// Split the unix timestamp `ping -D` prints in front of each line, e.g.
+/// "[1700000000.123456] 64 bytes from ...". `None` if the timestamp can't be read.
+fn split_timestamp(line: &str) -> Option<(Option<SystemTime>, &str)> {
+    let rest = match line.strip_prefix('[') {
+        Some(rest) => rest,
+        None => return Some((None, line)),
+    };
+    let (secs, line) = rest.split_once(']')?;
+    let secs = Duration::try_from_secs_f64(secs.parse().ok()?).ok()?;
+    Some((Some(UNIX_EPOCH.checked_add(secs)?), line.trim_start()))
+}
+
+/// The round trip time of a line of ping output, e.g. "time=9.5 ms" or "time<1ms"
+fn ping_time(line: &str) -> Option<Duration> {
+    let start = line.find("time=").or_else(|| line.find("time<"))? + "time=".len();
+    let value = &line[start..];
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    value[..end].parse().ok().and_then(ms)
+}
+
+/// Fill in the missing `timestamps`, `spacing` apart from the known ones. If none
+/// are known, the last one is now.
+fn fill_timestamps(timestamps: Vec<Option<SystemTime>>, spacing: Duration) -> Vec<SystemTime> {
+    let anchor = timestamps
+        .iter()
+        .enumerate()
+        .find_map(|(i, t)| t.map(|t| (i, t)));
+    let (anchor_index, anchor) =
+        anchor.unwrap_or_else(|| (timestamps.len().saturating_sub(1), SystemTime::now()));
+    let mut filled = Vec::with_capacity(timestamps.len());
+    for (i, t) in timestamps.iter().enumerate() {
+        let t = match t {
+            Some(t) => *t,
+            // before the first known timestamp, count back from it
+            None if i < anchor_index => spacing
+                .checked_mul((anchor_index - i) as u32)
+                .and_then(|before| anchor.checked_sub(before))
+                .unwrap_or(anchor),
+            None => filled
+                .last()
+                .map(|last: &SystemTime| last.checked_add(spacing).unwrap_or(*last))
+                .unwrap_or(anchor),
+        };
+        filled.push(t);
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINUX_PING: &str = "PING 8.8.8.8 (8.8.8.8) 56(84) bytes of data.
+64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=9.53 ms
+no answer yet for icmp_seq=2
+64 bytes from 8.8.8.8: icmp_seq=3 ttl=117 time=12 ms
+From 10.0.0.1 icmp_seq=4 Destination Host Unreachable
+
+--- 8.8.8.8 ping statistics ---
+4 packets transmitted, 2 received, 50% packet loss, time 3004ms
+rtt min/avg/max/mdev = 9.530/10.765/12.000/1.235 ms";
+
+    #[test]
+    fn ping_log() {
+        let imported = import_ping_log(LINUX_PING).unwrap();
+        assert_eq!(imported.len(), 4);
+        assert!(
+            matches!(&imported[0], Datapoint::Latency(target, Some(l), _, Some(1))
+            if target == "8.8.8.8" && *l == Duration::from_micros(9530))
+        );
+        assert!(matches!(
+            imported[1],
+            Datapoint::Latency(_, None, _, Some(2))
+        ));
+        assert!(matches!(imported[3], Datapoint::LatencyError(..)));
+        // evenly spaced, ending now
+        assert_eq!(
+            imported[1]
+                .timestamp()
+                .duration_since(imported[0].timestamp())
+                .unwrap(),
+            PING_INTERVAL
+        );
+        assert!(imported[3].timestamp() <= SystemTime::now());
+
+        let windows = "Pinging 1.1.1.1 with 32 bytes of data:
+Reply from 1.1.1.1: bytes=32 time<1ms TTL=57
+Request timed out.";
+        let imported = import_ping_log(windows).unwrap();
+        assert!(
+            matches!(&imported[0], Datapoint::Latency(target, Some(l), _, None) if target == "1.1.1.1" && *l == Duration::from_millis(1))
+        );
+        assert!(imported[1].is_timeout());
+
+        assert!(import_ping_log("hello").is_err());
+    }
+
+    #[test]
+    fn ping_log_timestamps() {
+        let text = "[1700000000.5] 64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=9 ms
+64 bytes from 8.8.8.8: icmp_seq=2 ttl=117 time=9 ms";
+        let imported = import_ping_log(text).unwrap();
+        assert_eq!(
+            imported[0].timestamp(),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)
+        );
+        assert_eq!(
+            imported[1].timestamp(),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_001_500)
+        );
+
+        // timestamps and times out of range are skipped instead of overflowing
+        let text = "[1e30] 64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=9 ms
+[-5] 64 bytes from 8.8.8.8: icmp_seq=2 ttl=117 time=9 ms
+64 bytes from 8.8.8.8: icmp_seq=3 ttl=117 time={} ms
+64 bytes from 8.8.8.8: icmp_seq=4 ttl=117 time=9 ms";
+        let imported = import_ping_log(&text.replace("{}", &"9".repeat(400))).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert!(matches!(
+            imported[0],
+            Datapoint::Latency(_, Some(_), _, Some(4))
+        ));
+    }
+
+    #[test]
+    fn speedtest_json() {
+        let ookla = r#"{"type":"result","timestamp":"2023-11-14T22:13:20Z","ping":{"jitter":0.5,"latency":12.5},
+            "download":{"bandwidth":12500000},"upload":{"bandwidth":2500000},"server":{"host":"speedtest.example.com"}}"#;
+        let imported = import_speedtest_json(ookla).unwrap();
+        assert_eq!(imported.len(), 3);
+        assert!(
+            matches!(&imported[0], Datapoint::Latency(server, Some(_), ..) if server == "speedtest.example.com")
+        );
+        assert!(matches!(imported[1], Datapoint::ThroughputDown(Some(d), _) if d == 100.));
+        assert!(matches!(imported[2], Datapoint::ThroughputUp(Some(u), _) if u == 20.));
+        assert_eq!(
+            imported[0].timestamp(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        let lines = "{\"download\": 50000000.0, \"upload\": 10000000.0, \"ping\": 20.1}
+{\"downloadSpeed\": 80, \"latency\": 15}";
+        let imported = import(lines).unwrap();
+        assert!(matches!(imported[1], Datapoint::ThroughputDown(Some(d), _) if d == 50.));
+        assert!(matches!(imported[4], Datapoint::ThroughputDown(Some(d), _) if d == 80.));
+        assert_eq!(
+            imported[3]
+                .timestamp()
+                .duration_since(imported[0].timestamp())
+                .unwrap(),
+            SPEEDTEST_INTERVAL
+        );
+
+        let imported = import_speedtest_json(r#"{"ping": 1e300, "download": 50000000.0}"#).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert!(matches!(imported[0], Datapoint::ThroughputDown(Some(_), _)));
+
+        assert!(import_speedtest_json("{\"foo\": 1}").is_err());
+    }
+}
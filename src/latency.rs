@@ -1,19 +1,210 @@
-use anyhow::Error;
-use log::debug;
-use pinger::{ping, PingResult};
-use std::time::Duration;
+use anyhow::{anyhow, Error};
+use log::{debug, warn};
+use pinger::{ping_with_interval, PingResult};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
 
-pub fn ping_callback<F: FnMut(Option<Duration>)>(addr: &str, mut callback: F) -> Result<(), Error> {
-    let stream = ping(addr.to_string())?;
+use super::{AddressFamily, PingBackend};
+
+/// Resolve `addr` to an IP address of the requested family, as the ping
+/// binary can't be told which family to use. With [AddressFamily::Auto] the
+/// address is passed on unchanged.
+pub fn resolve(addr: &str, family: AddressFamily) -> Result<String, Error> {
+    if family == AddressFamily::Auto {
+        return Ok(addr.to_string());
+    }
+    resolve_ip(addr, family).map(|ip| ip.to_string())
+}
+
+/// Resolve `addr` to an IP address of the requested family, or of any family with [AddressFamily::Auto]
+pub fn resolve_ip(addr: &str, family: AddressFamily) -> Result<IpAddr, Error> {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        if family.matches(&ip) {
+            return Ok(ip);
+        }
+        return Err(anyhow!("{} is not an {} address", addr, family));
+    }
+    (addr, 0)
+        .to_socket_addrs()?
+        .map(|socket_addr| socket_addr.ip())
+        .find(|ip| family.matches(ip))
+        .ok_or_else(|| match family {
+            AddressFamily::Auto => anyhow!("{} has no address", addr),
+            _ => anyhow!("{} has no {} address", addr, family),
+        })
+}
+
+/// Check that `addr` is an IP address or a hostname that resolves to an address of the given `family`
+pub fn check_target(addr: &str, family: AddressFamily) -> Result<(), Error> {
+    resolve_ip(addr, family).map(|_| ())
+}
+
+/// One router on the path to a target, see [traceroute]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HopResult {
+    /// Distance from this machine, starting at 1
+    pub ttl: u32,
+    /// The router that answered, `None` if none did in time
+    pub address: Option<IpAddr>,
+    /// Round trip time to the router
+    pub rtt: Option<Duration>,
+}
+
+/// The most routers [traceroute] asks before giving up
+#[cfg(feature = "icmp")]
+const TRACEROUTE_MAX_HOPS: u32 = 30;
+
+/// How long [traceroute] waits for each router to answer
+#[cfg(feature = "icmp")]
+const TRACEROUTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Find the routers on the path to `target` and the round trip time to each, like the
+/// `traceroute` tool. This needs the icmp feature and a raw ICMP socket, which usually needs root.
+pub fn traceroute(target: &str) -> Result<Vec<HopResult>, Error> {
+    #[cfg(feature = "icmp")]
+    {
+        let pinger = crate::icmp::IcmpPinger::new_raw(resolve_ip(target, AddressFamily::Auto)?)
+            .map_err(|e| {
+                anyhow!(
+                    "Can't open a raw ICMP socket, which usually needs root: {}",
+                    e
+                )
+            })?;
+        Ok(pinger.traceroute(TRACEROUTE_MAX_HOPS, TRACEROUTE_TIMEOUT)?)
+    }
+    #[cfg(not(feature = "icmp"))]
+    {
+        let _ = target;
+        Err(anyhow!(
+            "Traceroute needs linetest to be built with the icmp feature"
+        ))
+    }
+}
+
+/// Ping `addr` over the given address `family` every `interval` and call `callback` with the
+/// sequence number and result of each ping: the reply time or `None` for a timeout.
+/// Errors reported by ping, such as an unreachable host, are passed as `Err`.
+/// This keeps pinging until either `count` results have been delivered or the callback
+/// returns `false`. Returns an error if pinging stops before that.
+///
+/// With [PingBackend::Icmp] the echo requests are sent from this process. If it isn't
+/// allowed to open an ICMP socket, the system `ping` binary is used instead.
+pub fn ping_callback<F: FnMut(u64, Result<Option<Duration>, String>) -> bool>(
+    addr: &str,
+    family: AddressFamily,
+    backend: PingBackend,
+    interval: Duration,
+    count: Option<usize>,
+    callback: F,
+) -> Result<(), Error> {
+    if backend == PingBackend::Icmp {
+        #[cfg(feature = "icmp")]
+        match crate::icmp::IcmpPinger::new(resolve_ip(addr, family)?) {
+            Ok(pinger) => return pinger.ping_callback(interval, count, callback),
+            Err(e) => warn!("Can't open an ICMP socket ({}), using the system ping", e),
+        }
+        #[cfg(not(feature = "icmp"))]
+        warn!("Built without the icmp feature, using the system ping");
+    }
+    system_ping_callback(addr, family, interval, count, callback)
+}
+
+/// Like [ping_callback], keeping a single ping process running. Returns an error if
+/// the ping process exits early.
+fn system_ping_callback<F: FnMut(u64, Result<Option<Duration>, String>) -> bool>(
+    addr: &str,
+    family: AddressFamily,
+    interval: Duration,
+    count: Option<usize>,
+    mut callback: F,
+) -> Result<(), Error> {
+    let stream = ping_with_interval(resolve(addr, family)?, interval)?;
+    let mut received = 0;
+    let mut next_sequence = 0;
     for message in stream {
         debug!("Ping msg {}", message);
-        match message {
-            PingResult::Pong(duration, _) => callback(Some(duration)),
-            PingResult::Timeout(_) => callback(None),
-            // Unknown lines, just ignore.
-            PingResult::Unknown(_line) => (),
+        let (line, result) = match message {
+            PingResult::Pong(duration, line) => (line, Ok(Some(duration))),
+            PingResult::Timeout(line) => (line, Ok(None)),
+            PingResult::Unknown(line) => match ping_error(&line) {
+                Some(error) => (line, Err(error)),
+                // Other unknown lines, just ignore.
+                None => continue,
+            },
+        };
+        // not every system prints sequence numbers, count them in that case
+        let sequence = sequence_number(&line).unwrap_or(next_sequence);
+        next_sequence = sequence + 1;
+        let keep_going = callback(sequence, result);
+        received += 1;
+        if !keep_going || count.map(|c| received >= c).unwrap_or_default() {
+            return Ok(());
+        }
+    }
+    Err(anyhow!("ping {} exited unexpectedly", addr))
+}
+
+/// The ICMP sequence number in a line of ping output, e.g. "icmp_seq=3" or
+/// "Request timeout for icmp_seq 3"
+pub(crate) fn sequence_number(line: &str) -> Option<u64> {
+    let start = line.find("icmp_seq")? + "icmp_seq".len();
+    line[start..]
+        .trim_start_matches(['=', ' '])
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// The error message of a ping output line that reports a failed probe, such as
+/// "From 10.0.0.1 icmp_seq=1 Destination Host Unreachable".
+pub(crate) fn ping_error(line: &str) -> Option<String> {
+    let lower = line.to_lowercase();
+    ["unreachable", "unknown host", "not known", "exceeded"]
+        .iter()
+        .any(|pattern| lower.contains(pattern))
+        .then(|| line.trim().to_string())
+}
+
+/// Time the resolution of `hostname`. Returns `None` if it could not be resolved.
+pub fn dns_lookup(hostname: &str) -> Option<Duration> {
+    let t = Instant::now();
+    match (hostname, 0).to_socket_addrs() {
+        Ok(mut addrs) => addrs.next().map(|_| t.elapsed()),
+        Err(e) => {
+            debug!("Can't resolve {}: {}", hostname, e);
+            None
         }
-        return Ok(());
     }
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_lines() {
+        assert_eq!(
+            ping_error("From 10.0.0.1 icmp_seq=1 Destination Host Unreachable "),
+            Some("From 10.0.0.1 icmp_seq=1 Destination Host Unreachable".to_string())
+        );
+        assert_eq!(ping_error("Request timeout for icmp_seq 3"), None);
+        assert_eq!(
+            ping_error("PING 8.8.8.8 (8.8.8.8) 56(84) bytes of data."),
+            None
+        );
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        assert_eq!(
+            sequence_number("64 bytes from 8.8.8.8: icmp_seq=12 ttl=117 time=9.5 ms"),
+            Some(12)
+        );
+        assert_eq!(sequence_number("Request timeout for icmp_seq 3"), Some(3));
+        assert_eq!(
+            sequence_number("Reply from 8.8.8.8: bytes=32 time=9ms TTL=117"),
+            None
+        );
+    }
 }
@@ -0,0 +1,183 @@
+use anyhow::Error;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{IpAddr, UdpSocket},
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+/// Where a session was recorded. It is stored at the start of its log and read with
+/// [crate::load_metadata].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    /// The name of the machine
+    pub hostname: Option<String>,
+    /// The operating system, e.g. `linux` or `windows`
+    pub os: String,
+    /// A label given by the user, e.g. "coffee shop wifi"
+    pub label: Option<String>,
+    /// The local address of the interface that leads to the internet
+    pub local_address: Option<IpAddr>,
+    /// The address the connection has on the internet, see [Metadata::detect_isp]
+    pub public_address: Option<IpAddr>,
+    /// The provider of the connection, e.g. "AS3320 Deutsche Telekom AG"
+    pub isp: Option<String>,
+    /// Where the connection is located according to its public address
+    pub city: Option<String>,
+}
+
+/// The service the public address, provider and city are looked up with
+const ISP_LOOKUP_URL: &str = "https://ipinfo.io/json";
+
+/// The part of the answer of [ISP_LOOKUP_URL] that is kept
+#[derive(Debug, Clone, Deserialize)]
+struct IpInfo {
+    ip: Option<IpAddr>,
+    org: Option<String>,
+    city: Option<String>,
+}
+
+impl Metadata {
+    /// Describe this machine, along with an optional `label`
+    pub fn capture(label: Option<String>) -> Self {
+        Self {
+            hostname: hostname(),
+            os: std::env::consts::OS.to_string(),
+            label,
+            local_address: local_address(),
+            ..Default::default()
+        }
+    }
+
+    /// Look up the public address, provider and city of the connection with `agent`.
+    /// They stay `None` if the lookup fails.
+    pub fn detect_isp(&mut self, agent: &ureq::Agent) {
+        self.detect_isp_with(agent, ISP_LOOKUP_URL)
+    }
+
+    fn detect_isp_with(&mut self, agent: &ureq::Agent, url: &str) {
+        match lookup_isp(agent, url) {
+            Ok(info) => self.set_isp(info),
+            Err(e) => debug!("Can't look up the provider: {}", e),
+        }
+    }
+
+    fn set_isp(&mut self, info: IpInfo) {
+        self.public_address = info.ip;
+        self.isp = info.org;
+        self.city = info.city;
+    }
+}
+
+/// The provider of the connection, looked up once when a session starts and added to
+/// each new log of it, see [crate::MeasurementBuilder::detect_isp]. Clones share the result.
+#[derive(Debug, Clone, Default)]
+pub struct IspCache(Arc<Mutex<Option<IpInfo>>>);
+
+impl IspCache {
+    /// Look up the provider with `agent`. This blocks, so it is done by the measurement
+    /// threads. If the lookup fails, the provider of an earlier session is forgotten.
+    pub(crate) fn refresh(&self, agent: &ureq::Agent) {
+        self.refresh_with(agent, ISP_LOOKUP_URL)
+    }
+
+    fn refresh_with(&self, agent: &ureq::Agent, url: &str) {
+        let info = lookup_isp(agent, url)
+            .map_err(|e| debug!("Can't look up the provider: {}", e))
+            .ok();
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = info;
+    }
+
+    /// Add the provider to `metadata`, if it has been looked up
+    pub(crate) fn apply(&self, metadata: &mut Metadata) {
+        if let Some(info) = self.0.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+            metadata.set_isp(info);
+        }
+    }
+}
+
+fn lookup_isp(agent: &ureq::Agent, url: &str) -> Result<IpInfo, Error> {
+    Ok(serde_json::from_str(
+        &agent.get(url).call()?.into_string()?,
+    )?)
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "recorded on {}",
+            self.hostname.as_deref().unwrap_or("an unknown host")
+        )?;
+        if let Some(label) = &self.label {
+            write!(f, " ({})", label)?;
+        }
+        if let Some(isp) = &self.isp {
+            write!(f, " via {}", isp)?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of this machine, from the environment on Windows, the kernel on Linux
+/// or the `hostname` tool elsewhere
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            String::from_utf8(output.stdout).ok()
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// The address packets to the internet are sent from. Connecting a UDP socket
+/// only looks up the route, nothing is sent.
+fn local_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:53").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture() {
+        let metadata = Metadata::capture(Some("coffee shop wifi".to_string()));
+        assert_eq!(metadata.os, std::env::consts::OS);
+        assert!(metadata.to_string().ends_with(" (coffee shop wifi)"));
+        let unknown = Metadata::default();
+        assert_eq!(unknown.to_string(), "recorded on an unknown host");
+    }
+
+    #[test]
+    fn isp() {
+        let info: IpInfo = serde_json::from_str(
+            r#"{"ip": "203.0.113.7", "city": "Berlin", "country": "DE", "org": "AS3320 Deutsche Telekom AG"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.ip, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(info.city.as_deref(), Some("Berlin"));
+
+        // nothing listens on the discard port
+        let mut metadata = Metadata::default();
+        metadata.detect_isp_with(&ureq::agent(), "http://127.0.0.1:9/json");
+        assert_eq!(metadata, Metadata::default());
+
+        let cache = IspCache::default();
+        *cache.0.lock().unwrap() = Some(info);
+        cache.clone().apply(&mut metadata);
+        assert_eq!(metadata.isp.as_deref(), Some("AS3320 Deutsche Telekom AG"));
+        cache.refresh_with(&ureq::agent(), "http://127.0.0.1:9/json");
+        let mut metadata = Metadata::default();
+        cache.apply(&mut metadata);
+        assert_eq!(metadata, Metadata::default());
+    }
+}
@@ -0,0 +1,114 @@
+#[cfg(feature = "wifi")]
+use std::process::Command;
+
+/// The name of networks that can't be detected, see [crate::Evaluation::by_network]
+pub const UNKNOWN_NETWORK: &str = "unknown";
+
+/// The SSID of the WiFi network this machine is connected to. `None` if it is not on WiFi,
+/// the SSID can't be read on this platform or linetest is built without the `wifi` feature.
+pub fn current_network() -> Option<String> {
+    #[cfg(feature = "wifi")]
+    {
+        wifi_ssid()
+    }
+    #[cfg(not(feature = "wifi"))]
+    {
+        None
+    }
+}
+
+/// The standard output of a command, `None` if it can't be run or fails
+#[cfg(feature = "wifi")]
+fn output_of(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+#[cfg(all(feature = "wifi", target_os = "linux"))]
+fn wifi_ssid() -> Option<String> {
+    output_of("iwgetid", &["--raw"])
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty())
+        .or_else(|| {
+            output_of("nmcli", &["-t", "-f", "active,ssid", "dev", "wifi"])
+                .and_then(|out| parse_nmcli(&out))
+        })
+}
+
+#[cfg(all(feature = "wifi", target_os = "macos"))]
+fn wifi_ssid() -> Option<String> {
+    output_of("networksetup", &["-getairportnetwork", "en0"])
+        .and_then(|out| parse_networksetup(&out))
+}
+
+#[cfg(all(feature = "wifi", target_os = "windows"))]
+fn wifi_ssid() -> Option<String> {
+    output_of("netsh", &["wlan", "show", "interfaces"]).and_then(|out| parse_netsh(&out))
+}
+
+#[cfg(all(
+    feature = "wifi",
+    not(any(target_os = "linux", target_os = "macos", target_os = "windows"))
+))]
+fn wifi_ssid() -> Option<String> {
+    None
+}
+
+/// The active network of `nmcli -t -f active,ssid dev wifi`, whose lines look like `yes:home`
+#[cfg(feature = "wifi")]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_nmcli(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("yes:"))
+        .filter(|ssid| !ssid.is_empty())
+        .map(str::to_string)
+}
+
+/// The network of `networksetup -getairportnetwork`, e.g. `Current Wi-Fi Network: home`
+#[cfg(feature = "wifi")]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_networksetup(output: &str) -> Option<String> {
+    output
+        .trim()
+        .split_once("Network: ")
+        .map(|(_, ssid)| ssid.to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+/// The `SSID` line of `netsh wlan show interfaces`, not to be confused with the `BSSID` line
+#[cfg(feature = "wifi")]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn parse_netsh(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "SSID" && !value.trim().is_empty()).then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(all(test, feature = "wifi"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ssids() {
+        assert_eq!(
+            parse_nmcli("no:neighbour\nyes:home\n"),
+            Some("home".to_string())
+        );
+        assert_eq!(parse_nmcli("no:neighbour\n"), None);
+        assert_eq!(
+            parse_networksetup("Current Wi-Fi Network: coffee shop\n"),
+            Some("coffee shop".to_string())
+        );
+        assert_eq!(
+            parse_networksetup("You are not associated with an AirPort network.\n"),
+            None
+        );
+        let netsh = "    Name                   : Wi-Fi\n    SSID                   : home\n    BSSID                  : 12:34:56:78:9a:bc\n";
+        assert_eq!(parse_netsh(netsh), Some("home".to_string()));
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::Error;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::metadata,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    append_datapoint,
+    eval::{append_metadata, trim_jsonl},
+    Datapoint, MeasurementBuilder, Metadata,
+};
+
+/// When to continue a recording in a new logfile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RotationPolicy {
+    /// Keep writing to the same file
+    #[default]
+    Never,
+    /// Start a new file when the (UTC) day changes
+    Daily,
+    /// Start a new file once the current one has grown to this many bytes
+    MaxBytes(u64),
+}
+
+impl RotationPolicy {
+    /// Returns true if the log at `path` should not be written to anymore
+    fn should_rotate(&self, path: &Path) -> bool {
+        let meta = match metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => return false,
+        };
+        match self {
+            RotationPolicy::Never => false,
+            RotationPolicy::Daily => meta
+                .modified()
+                .map(|modified| utc_day(modified) != utc_day(SystemTime::now()))
+                .unwrap_or_default(),
+            RotationPolicy::MaxBytes(max) => meta.len() >= *max,
+        }
+    }
+}
+
+/// How much a logfile keeps before its oldest datapoints are dropped, see
+/// [MeasurementBuilder::flight_recorder]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogCapacity {
+    /// Keep at least this many of the most recent datapoints. To keep appending cheap, the
+    /// log grows to about twice as many before the oldest ones are dropped at once.
+    Datapoints(usize),
+    /// Keep the log below this many bytes. Once it is full, the oldest half is dropped.
+    Bytes(u64),
+}
+
+impl LogCapacity {
+    /// Returns true if the log at `path` is full, after `dp` was appended to it. Only the size
+    /// of the log is checked, the number of datapoints is estimated from the size of `dp`.
+    /// A log that was `trimmed_len` bytes long after its last trim is only full again once
+    /// it doubled, so a misleading estimate doesn't rewrite it on every append.
+    fn is_full(
+        &self,
+        path: &Path,
+        dp: &Datapoint,
+        trimmed_len: Option<u64>,
+    ) -> Result<bool, Error> {
+        let len = metadata(path)?.len();
+        if len <= 2 * trimmed_len.unwrap_or_default() {
+            return Ok(false);
+        }
+        Ok(match self {
+            LogCapacity::Datapoints(max) => {
+                let line_len = serde_json::to_vec(dp)?.len() as u64 + 1;
+                len > 2 * *max as u64 * line_len
+            }
+            LogCapacity::Bytes(max) => len > *max,
+        })
+    }
+}
+
+/// Days since the unix epoch in UTC
+fn utc_day(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / (24 * 60 * 60)
+}
+
+/// Add a counter to the name of `path` until no such file exists
+fn unique_path(path: PathBuf) -> PathBuf {
+    let mut candidate = path.clone();
+    let mut counter = 1;
+    while candidate.exists() {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        candidate = path.with_file_name(format!("{}-{}.{}", stem, counter, super::LOG_EXTENSION));
+        counter += 1;
+    }
+    candidate
+}
+
+impl MeasurementBuilder {
+    /// Append a datapoint to the logfile, if there is one. If the [RotationPolicy]
+    /// triggers, a new timestamped logfile is started first. Returns true in that case.
+    /// The oldest datapoints are dropped once the log exceeds its [LogCapacity].
+    pub fn append_to_log(&mut self, dp: &Datapoint) -> Result<bool, Error> {
+        let rotate = match &self.logfile {
+            Some(log) => self.rotation.should_rotate(log),
+            None => return Ok(false),
+        };
+        if rotate {
+            let new_log = unique_path(self.new_logfile());
+            info!("Continuing the recording in {}", new_log.display());
+            self.logfile = Some(new_log);
+            self.trimmed_log = None;
+        }
+        if let Some(log) = &self.logfile {
+            if !log.exists() {
+                let mut metadata = Metadata::capture(self.label.clone());
+                if self.detect_isp {
+                    self.isp.apply(&mut metadata);
+                }
+                append_metadata(log, &metadata)?;
+            }
+            append_datapoint(log, dp)?;
+            if let Some(capacity) = self.log_capacity {
+                let trimmed_len = match &self.trimmed_log {
+                    Some((trimmed, len)) if trimmed == log => Some(*len),
+                    _ => None,
+                };
+                if capacity.is_full(log, dp, trimmed_len)? {
+                    trim_jsonl(log, capacity)?;
+                    self.trimmed_log = Some((log.clone(), metadata(log)?.len()));
+                }
+            }
+        }
+        Ok(rotate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Evaluation;
+    use std::time::Duration;
+
+    #[test]
+    fn without_log() {
+        let dir = std::env::temp_dir().join("linetest_without_log");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut measurement = MeasurementBuilder::default()
+            .with_data_dir(&dir)
+            .without_log();
+        assert!(!measurement
+            .append_to_log(&Datapoint::add_latency("8.8.8.8", None))
+            .unwrap());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let path = std::env::temp_dir().join("linetest_rotate_by_size.ltst");
+        let _ = std::fs::remove_file(&path);
+        let mut measurement = MeasurementBuilder {
+            rotation: RotationPolicy::MaxBytes(1),
+            data_dir: Some(std::env::temp_dir()),
+            logfile: Some(path.clone()),
+            ..Default::default()
+        };
+        assert!(!measurement
+            .append_to_log(&Datapoint::add_latency("8.8.8.8", None))
+            .unwrap());
+        assert!(measurement
+            .append_to_log(&Datapoint::add_latency("8.8.8.8", None))
+            .unwrap());
+        let new_log = measurement.logfile.clone().unwrap();
+        assert_ne!(new_log, path);
+        assert_eq!(new_log.parent(), Some(std::env::temp_dir().as_path()));
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(new_log);
+    }
+
+    #[test]
+    fn flight_recorder() {
+        let path = std::env::temp_dir().join("linetest_flight_recorder.ltst");
+        let _ = std::fs::remove_file(&path);
+        let mut measurement = MeasurementBuilder::default().flight_recorder(10);
+        measurement.logfile = Some(path.clone());
+        for ms in 0..100 {
+            measurement
+                .append_to_log(&Datapoint::add_latency(
+                    "8.8.8.8",
+                    Some(Duration::from_millis(ms)),
+                ))
+                .unwrap();
+        }
+        let mut log = vec![];
+        log.load(&path).unwrap();
+        assert!(
+            log.len() >= 10 && log.len() <= 20,
+            "{} datapoints",
+            log.len()
+        );
+        assert!(
+            matches!(log.last(), Some(Datapoint::Latency(_, Some(l), ..)) if *l == Duration::from_millis(99))
+        );
+        // the metadata stays at the start
+        assert!(crate::load_metadata(&path).unwrap().is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn trim_after_growth() {
+        let path = std::env::temp_dir().join("linetest_trim_after_growth.ltst");
+        let _ = std::fs::remove_file(&path);
+        let long_target = "a".repeat(500);
+        for _ in 0..5 {
+            append_datapoint(&path, &Datapoint::add_latency(&long_target, None)).unwrap();
+        }
+        // the short datapoint suggests far more datapoints than the log has
+        let short = Datapoint::add_latency("8.8.8.8", None);
+        let capacity = LogCapacity::Datapoints(10);
+        assert!(capacity.is_full(&path, &short, None).unwrap());
+        let len = metadata(&path).unwrap().len();
+        assert!(!capacity.is_full(&path, &short, Some(len)).unwrap());
+        for _ in 0..6 {
+            append_datapoint(&path, &Datapoint::add_latency(&long_target, None)).unwrap();
+        }
+        assert!(capacity.is_full(&path, &short, Some(len)).unwrap());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn byte_capacity() {
+        let path = std::env::temp_dir().join("linetest_byte_capacity.ltst");
+        let _ = std::fs::remove_file(&path);
+        let mut measurement = MeasurementBuilder {
+            log_capacity: Some(LogCapacity::Bytes(2000)),
+            logfile: Some(path.clone()),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            measurement
+                .append_to_log(&Datapoint::add_latency("8.8.8.8", None))
+                .unwrap();
+            assert!(metadata(&path).unwrap().len() <= 2000);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn unique_names() {
+        let path = std::env::temp_dir().join("linetest_unique_names.ltst");
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(
+            unique_path(path.clone()),
+            std::env::temp_dir().join("linetest_unique_names-1.ltst")
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
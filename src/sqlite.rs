@@ -0,0 +1,121 @@
+use anyhow::Error;
+use rusqlite::{params, Connection, OpenFlags};
+use std::{
+    fs::create_dir_all,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{eval::flat_fields, Datapoint, MeasurementResult};
+
+/// One row per datapoint. The flattened columns are there to query with SQL,
+/// the datapoint itself is stored as JSON so it can be loaded without loss.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS datapoints (
+        id INTEGER PRIMARY KEY,
+        timestamp_ms INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        value REAL,
+        source TEXT NOT NULL,
+        datapoint TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS datapoints_timestamp ON datapoints (timestamp_ms);
+";
+
+fn unix_ms(t: &SystemTime) -> Result<i64, Error> {
+    Ok(t.duration_since(UNIX_EPOCH)?.as_millis() as i64)
+}
+
+/// Replace the contents of the database at `path` with `result`
+pub(crate) fn save<P: AsRef<Path>>(result: &MeasurementResult, path: P) -> Result<(), Error> {
+    if let Some(parent) = path.as_ref().parent() {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            create_dir_all(parent)?;
+        }
+    }
+    let mut connection = Connection::open(path.as_ref())?;
+    connection.execute_batch(SCHEMA)?;
+    let transaction = connection.transaction()?;
+    transaction.execute("DELETE FROM datapoints", [])?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO datapoints (timestamp_ms, kind, value, source, datapoint) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for dp in result {
+            let (kind, value, t, source) = flat_fields(dp);
+            insert.execute(params![
+                unix_ms(t)?,
+                kind,
+                value,
+                source,
+                serde_json::to_string(dp)?
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Read the datapoints recorded from `start` (inclusive) up to `end` (exclusive)
+/// from the database at `path`, sorted by time
+pub fn query_between<P: AsRef<Path>>(
+    path: P,
+    start: SystemTime,
+    end: SystemTime,
+) -> Result<MeasurementResult, Error> {
+    query(
+        path,
+        "SELECT datapoint FROM datapoints WHERE timestamp_ms >= ?1 AND timestamp_ms < ?2 ORDER BY timestamp_ms, id",
+        (unix_ms(&start)?, unix_ms(&end)?),
+    )
+}
+
+/// Read all datapoints from the database at `path`, sorted by time
+pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<MeasurementResult, Error> {
+    query(
+        path,
+        "SELECT datapoint FROM datapoints ORDER BY timestamp_ms, id",
+        (),
+    )
+}
+
+fn query<P: AsRef<Path>, A: rusqlite::Params>(
+    path: P,
+    sql: &str,
+    args: A,
+) -> Result<MeasurementResult, Error> {
+    let connection = Connection::open_with_flags(path.as_ref(), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(args, |row| row.get::<_, String>(0))?;
+    let mut result = vec![];
+    for row in rows {
+        result.push(serde_json::from_str::<Datapoint>(&row?)?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Evaluation;
+    use std::time::Duration;
+
+    #[test]
+    fn sqlite_roundtrip() {
+        let path = std::env::temp_dir().join("linetest_sqlite_roundtrip.sqlite");
+        let start = SystemTime::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let result: MeasurementResult = (0..4)
+            .map(|i| Datapoint::Latency("a".to_string(), None, at(i), None))
+            .collect();
+        result.save_sqlite(&path).unwrap();
+        // saving again replaces the previous contents
+        result.save_sqlite(&path).unwrap();
+
+        let mut loaded: MeasurementResult = vec![];
+        loaded.load_sqlite(&path).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(query_between(&path, at(1), at(3)).unwrap().len(), 2);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::Error;
+use log::{debug, error, info};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task, time,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+use super::{
+    latency, throughput, throughput_test_urls, url_download_datapoints, AddressFamily, Datapoint,
+    MeasurementBuilder, PING_RETRY_MAX_BACKOFF,
+};
+
+/// How many datapoints may wait in the stream before the measurement pauses
+const STREAM_BUFFER: usize = 64;
+
+impl MeasurementBuilder {
+    /// Start the measurement on the current Tokio runtime and stream its [Datapoint]s, so it
+    /// composes with other futures, e.g. in `tokio::select!`. DNS lookups, downloads and
+    /// uploads are async and time out with Tokio's timers. The pings come from the system
+    /// `ping` or a blocking ICMP socket, so they run on Tokio's blocking threads.
+    /// The measurement stops when the stream is dropped. Fails outside of a Tokio runtime.
+    ///
+    /// Unlike [MeasurementBuilder::run_advanced] it downloads each url over a single
+    /// connection and doesn't pick a server from `server_candidates`, use iperf3 or ping
+    /// during downloads.
+    pub fn run_stream(&self) -> Result<impl Stream<Item = Datapoint>, Error> {
+        let runtime = tokio::runtime::Handle::try_current()?;
+        let client = self.client()?;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        runtime.spawn(measure(self.clone(), client, sender));
+        Ok(ReceiverStream::new(receiver))
+    }
+
+    /// Create the async http client for throughput tests, like [MeasurementBuilder::agent]
+    fn client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder().connect_timeout(self.download_timeout);
+        // connecting from an address of the family only allows addresses of that family
+        builder = match self.address_family {
+            AddressFamily::Auto => builder,
+            AddressFamily::V4 => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            AddressFamily::V6 => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        };
+        if let Some(proxy) = &self.proxy {
+            throughput::proxy(proxy)?;
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Run the tests of `measurement` in cycles like [MeasurementBuilder::run_advanced] and send
+/// the datapoints to `sender` until it is closed
+async fn measure(
+    measurement: MeasurementBuilder,
+    client: reqwest::Client,
+    sender: mpsc::Sender<Datapoint>,
+) {
+    info!("Start async measurement");
+    let ping_targets = measurement.ping_targets();
+    let download_settings = measurement.download_settings();
+    // how long to wait before pinging a target again after its ping process failed
+    let mut ping_backoff = vec![Duration::ZERO; ping_targets.len()];
+    // every batch starts a new ping process, so its sequence numbers continue from here
+    let mut ping_sequence_offset = vec![0; ping_targets.len()];
+    let mut downloaded_bytes = 0;
+    let mut last_mbits = None;
+    'measure: loop {
+        for hostname in &measurement.dns_targets {
+            let lookup = Datapoint::add_dns_lookup(hostname, dns_lookup(hostname).await);
+            if sender.send(lookup).await.is_err() {
+                break 'measure;
+            }
+        }
+
+        // ping all targets at once, each at most until the next throughput test
+        let batches = ping_targets
+            .iter()
+            .zip(&ping_backoff)
+            .zip(&ping_sequence_offset)
+            .map(|((target, backoff), offset)| {
+                tokio::spawn(ping_batch(
+                    measurement.clone(),
+                    target.clone(),
+                    *backoff,
+                    *offset,
+                    sender.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        for ((batch, backoff), offset) in batches
+            .into_iter()
+            .zip(ping_backoff.iter_mut())
+            .zip(ping_sequence_offset.iter_mut())
+        {
+            if let Ok((next_backoff, next_sequence)) = batch.await {
+                *backoff = next_backoff;
+                *offset = next_sequence;
+            }
+        }
+        if sender.is_closed() {
+            break;
+        }
+
+        let budget_exceeded = measurement
+            .data_budget
+            .map(|budget| downloaded_bytes >= budget)
+            .unwrap_or_default();
+        if budget_exceeded {
+            continue;
+        }
+        let test_urls = throughput_test_urls(
+            &measurement.downloads_urls,
+            &measurement.payload_sizes,
+            &measurement.payload_url_template,
+            last_mbits,
+        );
+        if !test_urls.is_empty() {
+            let results = download_each(
+                &client,
+                &test_urls,
+                measurement.download_timeout,
+                measurement.download_retries,
+                measurement.max_parallel_downloads,
+            )
+            .await;
+            let (datapoints, bytes) = url_download_datapoints(results, &download_settings);
+            downloaded_bytes += bytes;
+            if let Some(budget) = measurement.data_budget.filter(|b| downloaded_bytes >= *b) {
+                info!(
+                    "Data budget of {} bytes used up ({} bytes downloaded), stopping throughput tests",
+                    budget, downloaded_bytes
+                );
+            }
+            for dp in datapoints {
+                if let Datapoint::ThroughputDown(Some(mbits), _) = dp {
+                    last_mbits = Some(mbits);
+                }
+                if sender.send(dp).await.is_err() {
+                    break 'measure;
+                }
+            }
+        }
+
+        if measurement.upload_urls.is_empty() {
+            continue;
+        }
+        let upload = upload_each(
+            &client,
+            &measurement.upload_urls,
+            measurement.upload_payload_size,
+            measurement.download_timeout,
+        )
+        .await
+        .ok()
+        .map(throughput::to_mbits);
+        if sender.send(Datapoint::add_tp_up(upload)).await.is_err() {
+            break;
+        }
+    }
+    info!("Stopping async measurement");
+}
+
+/// Ping `target` for one batch of latency tests after waiting for `backoff`, with sequence
+/// numbers from `offset`, and send the samples to `sender`. Returns the backoff before the
+/// next batch and the sequence number it continues from.
+async fn ping_batch(
+    measurement: MeasurementBuilder,
+    target: String,
+    backoff: Duration,
+    offset: u64,
+    sender: mpsc::Sender<Datapoint>,
+) -> (Duration, u64) {
+    time::sleep(backoff).await;
+    let label = measurement.address_family.label(&target);
+    let ping_delay = measurement.ping_delay;
+    let pings_per_sample = measurement.pings_per_sample.max(1);
+    let ping_sender = sender.clone();
+    let ping_label = label.clone();
+    let batch = task::spawn_blocking(move || {
+        let mut sample = vec![];
+        let mut next_sequence = offset;
+        let res = latency::ping_callback(
+            &target,
+            measurement.address_family,
+            measurement.ping_backend,
+            ping_delay,
+            Some(measurement.throughput_ping_ratio * pings_per_sample),
+            |sequence, duration_result| {
+                let sequence = offset + sequence;
+                next_sequence = next_sequence.max(sequence + 1);
+                match duration_result {
+                    Ok(latency) => sample.push((sequence, latency)),
+                    Err(e) => {
+                        return ping_sender
+                            .blocking_send(Datapoint::add_latency_error(&ping_label, &e))
+                            .is_ok();
+                    }
+                }
+                if sample.len() < pings_per_sample {
+                    return !ping_sender.is_closed();
+                }
+                let mut sent = true;
+                for (sequence, latency) in measurement.ping_aggregation.aggregate_sequenced(&sample)
+                {
+                    sent &= ping_sender
+                        .blocking_send(Datapoint::add_sequenced_latency(
+                            &ping_label,
+                            latency,
+                            sequence,
+                        ))
+                        .is_ok();
+                }
+                sample.clear();
+                sent
+            },
+        );
+        (res, next_sequence)
+    });
+    match batch.await {
+        Ok((Ok(()), next_sequence)) => (Duration::ZERO, next_sequence),
+        Ok((Err(e), next_sequence)) => {
+            // retry no faster than pings are sent, and slower the longer it fails
+            let backoff = (backoff * 2)
+                .max(ping_delay)
+                .min(PING_RETRY_MAX_BACKOFF.max(ping_delay));
+            error!("Ping {} failed: {}, retrying in {:?}", label, e, backoff);
+            let _ = sender
+                .send(Datapoint::add_latency_error(&label, &e.to_string()))
+                .await;
+            (backoff, next_sequence)
+        }
+        Err(e) => {
+            error!("Ping {} stopped: {}", label, e);
+            (backoff, offset)
+        }
+    }
+}
+
+/// Time the resolution of `hostname`, like [latency::dns_lookup]
+async fn dns_lookup(hostname: &str) -> Option<Duration> {
+    let t = Instant::now();
+    match tokio::net::lookup_host((hostname, 0)).await {
+        Ok(mut addrs) => addrs.next().map(|_| t.elapsed()),
+        Err(e) => {
+            debug!("Can't resolve {}: {}", hostname, e);
+            None
+        }
+    }
+}
+
+/// Retrieve a file with the async `client`, giving up if the response or the next part of
+/// the file doesn't arrive within `timeout`. Returns the time and bytes and the HTTP version.
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    timeout: Duration,
+) -> Result<((Duration, usize), String), Error> {
+    let t = Instant::now();
+    let mut response = time::timeout(timeout, client.get(url).send())
+        .await??
+        .error_for_status()?;
+    let protocol = format!("{:?}", response.version());
+    let mut bytes = 0;
+    while let Some(chunk) = time::timeout(timeout, response.chunk()).await?? {
+        bytes += chunk.len();
+    }
+    Ok(((t.elapsed(), bytes), protocol))
+}
+
+/// Retrieve multiple files at once like [throughput::measured_download_each], at most
+/// `max_parallel` at a time (all at once if it is 0). Each download is retried up to
+/// `retries` times with exponential backoff, which is left out of its completion time.
+async fn download_each(
+    client: &reqwest::Client,
+    urls: &[String],
+    timeout: Duration,
+    retries: u32,
+    max_parallel: usize,
+) -> Vec<throughput::UrlDownload> {
+    let start = Instant::now();
+    let permits = Arc::new(Semaphore::new(if max_parallel == 0 {
+        urls.len().max(1)
+    } else {
+        max_parallel
+    }));
+    let downloads = urls
+        .iter()
+        .map(|url| {
+            let client = client.clone();
+            let url = url.clone();
+            let permits = permits.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let first_attempt = Instant::now();
+                let mut backoff = throughput::RETRY_BACKOFF;
+                let mut attempt = 0;
+                loop {
+                    let retried = first_attempt.elapsed();
+                    let result = download(&client, &url, timeout).await;
+                    let elapsed = start.elapsed().saturating_sub(retried);
+                    match result {
+                        Ok((res, protocol)) => return (url, Ok(res), elapsed, Some(protocol)),
+                        Err(e) if attempt < retries => {
+                            attempt += 1;
+                            debug!(
+                                "Download of {} failed ({}), retry {}/{} in {:?}",
+                                url, e, attempt, retries, backoff
+                            );
+                            time::sleep(backoff).await;
+                            backoff *= 2;
+                        }
+                        Err(e) => return (url, Err(e), elapsed, None),
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut results = vec![];
+    for (url, download) in urls.iter().zip(downloads) {
+        results.push(
+            download
+                .await
+                .unwrap_or_else(|e| (url.clone(), Err(e.into()), start.elapsed(), None)),
+        );
+    }
+    results
+}
+
+/// Send a generated payload of `payload_size` bytes to all urls at once, like
+/// [throughput::combined_upload]. Each upload may take up to `timeout`.
+async fn upload_each(
+    client: &reqwest::Client,
+    urls: &[String],
+    payload_size: usize,
+    timeout: Duration,
+) -> Result<(Duration, usize), Error> {
+    let payload = throughput::upload_payload(payload_size);
+    let t = Instant::now();
+    let uploads = urls
+        .iter()
+        .map(|url| {
+            let request = client
+                .post(url)
+                .header("Content-Type", "application/octet-stream")
+                .timeout(timeout)
+                .body(payload.clone());
+            tokio::spawn(async move {
+                // wait for the whole response so the request is complete before we stop the clock
+                request.send().await?.error_for_status()?.bytes().await?;
+                Ok::<_, Error>((Duration::ZERO, payload_size))
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut results = vec![];
+    for upload in uploads {
+        results.push(upload.await.unwrap_or_else(|e| Err(e.into())));
+    }
+    throughput::combine(t.elapsed(), results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn stream_datapoints() {
+        let measurement = MeasurementBuilder {
+            ping_ips: vec!["127.0.0.1".to_string()],
+            ..MeasurementBuilder::default().light()
+        };
+        let mut stream = Box::pin(measurement.run_stream().unwrap());
+        assert!(stream.next().await.is_some());
+    }
+
+    #[test]
+    fn outside_runtime() {
+        assert!(MeasurementBuilder::default().run_stream().is_err());
+    }
+
+    #[tokio::test]
+    async fn async_download() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0; 1024]).await;
+            let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 100000\r\n\r\n".to_vec();
+            response.resize(response.len() + 100_000, 0);
+            let _ = stream.write_all(&response).await;
+        });
+        let client = MeasurementBuilder::default().client().unwrap();
+        let results = download_each(&client, &[url], Duration::from_secs(5), 0, 0).await;
+        let (_, result, _, protocol) = &results[0];
+        assert_eq!(result.as_ref().unwrap().1, 100_000);
+        assert_eq!(protocol.as_deref(), Some("HTTP/1.1"));
+    }
+}
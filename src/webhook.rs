@@ -0,0 +1,206 @@
+use anyhow::Error;
+use log::{debug, warn};
+use std::{
+    sync::mpsc::{channel, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+use ureq::Agent;
+
+use super::{Datapoint, MeasurementHandle};
+
+/// Delay before retrying a failed post. It doubles with every further failure.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay between retries of a failed post
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// The most datapoints kept while the webhook can't be reached. Older ones are dropped.
+const MAX_PENDING: usize = 10_000;
+
+/// The shortest time to wait for datapoints while none are pending
+const MIN_WAIT: Duration = Duration::from_millis(100);
+
+/// Collects datapoints and posts them to an url as a JSON array, in batches
+pub(crate) struct Webhook {
+    agent: Agent,
+    url: String,
+    batch_size: usize,
+    flush_interval: Duration,
+    pending: Vec<Datapoint>,
+    last_flush: Instant,
+    backoff: Duration,
+    /// After a failed post, the next one is not attempted before this time
+    retry_at: Option<Instant>,
+}
+
+impl Webhook {
+    pub fn new(agent: Agent, url: &str, batch_size: usize, flush_interval: Duration) -> Self {
+        Self {
+            agent,
+            url: url.to_string(),
+            batch_size,
+            flush_interval,
+            pending: vec![],
+            last_flush: Instant::now(),
+            backoff: RETRY_BACKOFF,
+            retry_at: None,
+        }
+    }
+
+    pub fn push(&mut self, dp: Datapoint) {
+        self.pending.push(dp);
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            warn!(
+                "Webhook {} can't be reached, dropped {} datapoints",
+                self.url, dropped
+            );
+        }
+    }
+
+    /// Time until the pending datapoints are due to be posted
+    pub fn until_due(&self) -> Duration {
+        if self.pending.is_empty() {
+            return self.flush_interval.max(MIN_WAIT);
+        }
+        self.retry_at
+            .unwrap_or(self.last_flush + self.flush_interval)
+            .saturating_duration_since(Instant::now())
+    }
+
+    /// Post the pending datapoints once there is a full batch or the flush interval has
+    /// passed, unless a failed post is still waiting to be retried
+    pub fn flush_if_due(&mut self) {
+        let now = Instant::now();
+        let due = match self.retry_at {
+            Some(retry_at) => now >= retry_at,
+            None => {
+                self.pending.len() >= self.batch_size
+                    || now >= self.last_flush + self.flush_interval
+            }
+        };
+        if due {
+            self.flush();
+        }
+    }
+
+    /// Post all pending datapoints. If that fails they are kept for the next attempt.
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match self.post() {
+            Ok(()) => {
+                debug!("Posted {} datapoints to {}", self.pending.len(), self.url);
+                self.pending.clear();
+                self.retry_at = None;
+                self.backoff = RETRY_BACKOFF;
+            }
+            Err(e) => {
+                warn!(
+                    "Can't post {} datapoints to {}: {}, retrying in {:?}",
+                    self.pending.len(),
+                    self.url,
+                    e,
+                    self.backoff
+                );
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_RETRY_BACKOFF);
+            }
+        }
+        self.last_flush = Instant::now();
+    }
+
+    fn post(&self) -> Result<(), Error> {
+        let body = serde_json::to_string(&self.pending)?;
+        self.agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)?;
+        Ok(())
+    }
+}
+
+/// Pass the datapoints of `measurement` on to the returned handle and post them to `webhook`
+pub(crate) fn forward(
+    mut measurement: MeasurementHandle,
+    mut webhook: Webhook,
+) -> MeasurementHandle {
+    let stop_flag = measurement.stop_flag();
+    let (sender, receiver) = channel();
+
+    let thread = thread::spawn(move || {
+        loop {
+            match measurement.recv_timeout(webhook.until_due()) {
+                Ok(dp) => {
+                    webhook.push(dp.clone());
+                    if sender.send(dp).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            webhook.flush_if_due();
+        }
+        let result = measurement.conclude();
+        // one last attempt for the rest
+        webhook.flush();
+        result
+    });
+
+    MeasurementHandle::new(receiver, stop_flag, thread)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn post_batches() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/collect",
+            listener.local_addr().unwrap().port()
+        );
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(reader.get_mut(), "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            serde_json::from_slice::<Vec<Datapoint>>(&body).unwrap()
+        });
+
+        let mut webhook = Webhook::new(ureq::agent(), &url, 2, Duration::from_secs(60));
+        webhook.push(Datapoint::add_latency("8.8.8.8", None));
+        webhook.flush_if_due();
+        // the batch is not full yet
+        assert_eq!(webhook.pending.len(), 1);
+        webhook.push(Datapoint::add_tp_down(Some(10.)));
+        webhook.flush_if_due();
+        assert!(webhook.pending.is_empty());
+        assert_eq!(server.join().unwrap().len(), 2);
+
+        // nothing listens here anymore, so the datapoints are kept for a retry
+        webhook.push(Datapoint::add_latency("8.8.8.8", None));
+        webhook.flush();
+        assert_eq!(webhook.pending.len(), 1);
+        assert!(webhook.retry_at.is_some());
+    }
+}